@@ -1,8 +1,160 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
 use rand::prelude::*;
 
-use ultraviolet::{Mat4, Vec3, Vec4};
+use ultraviolet::{Mat4, Vec2, Vec3, Vec4};
 
+use crate::lens::Lens;
+use crate::materials::orthonormal_basis;
+use crate::pixmap::PixMap;
 use crate::ray::Ray;
+use crate::world::World;
+use crate::Hittable;
+
+// The shape of the lens opening, which out of focus highlights take on.
+#[derive(Clone)]
+pub enum Aperture {
+    Circle,
+    // Number of blades and their rotation in radians.
+    Polygon(u32, f32),
+    // A grayscale mask over the opening, passing light in proportion to its brightness.
+    Image(Arc<PixMap>),
+}
+
+impl Aperture {
+    // A random point on the opening, within the unit disk.
+    pub fn sample(&self) -> Vec3 {
+        match self {
+            Aperture::Circle => random_in_unit_disk(),
+            Aperture::Polygon(blades, rotation) => {
+                let mut rng = thread_rng();
+                let blades = (*blades).max(3);
+                let side = rng.gen_range(0, blades) as f32;
+                let corner = |i: f32| {
+                    let angle = rotation + 2.0 * PI * i / blades as f32;
+                    Vec3::new(angle.cos(), angle.sin(), 0.0)
+                };
+
+                // A uniform point in the triangle between the center and one side.
+                let (mut a, mut b) = (rng.gen::<f32>(), rng.gen::<f32>());
+                if a + b > 1.0 {
+                    a = 1.0 - a;
+                    b = 1.0 - b;
+                }
+
+                a * corner(side) + b * corner(side + 1.0)
+            }
+            Aperture::Image(mask) => {
+                let mut rng = thread_rng();
+
+                for _ in 0..64 {
+                    let p = random_in_unit_disk();
+                    let c = mask.sample((p.x + 1.0) / 2.0, (p.y + 1.0) / 2.0);
+
+                    if rng.gen::<f32>() < (c.x + c.y + c.z) / 3.0 {
+                        return p;
+                    }
+                }
+
+                Vec3::zero()
+            }
+        }
+    }
+}
+
+// How directions around the camera map onto the frame.
+#[derive(Clone, Copy, Debug)]
+pub enum Projection {
+    Perspective,
+    // Parallel rays over a view of the given height in world units.
+    Orthographic(f32),
+    // Equidistant fisheye with the given field of view in radians across the frame height.
+    Fisheye(f32),
+    // The full sphere around the camera, in a frame twice as wide as it is tall.
+    Equirectangular,
+    // Six 90 degree faces, +x -x +y on the top row and -y +z -z below, oriented as OpenGL
+    // cube maps expect.
+    Cubemap,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Shutter {
+    // The whole frame is exposed at once.
+    Global,
+    // Rows are exposed one after another from the top, with the last starting the given
+    // readout time after the first, skewing anything moving across the frame.
+    Rolling(f32),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum StereoLayout {
+    // The left eye on the left half of the frame.
+    SideBySide,
+    // The left eye on the top half of the frame.
+    TopBottom,
+}
+
+// Renders a left and right eye view side by side or one above the other. Perspective and
+// orthographic eyes are offset sideways with their frustums skewed to meet at the
+// convergence distance, while equirectangular eyes follow a circle the width of the
+// interocular distance for omni-directional stereo.
+#[derive(Clone, Copy, Debug)]
+pub struct Stereo {
+    pub interocular: f32,
+    pub convergence: f32,
+    pub layout: StereoLayout,
+}
+
+impl Stereo {
+    pub fn new(interocular: f32, convergence: f32, layout: StereoLayout) -> Self {
+        Self {
+            interocular,
+            convergence,
+            layout,
+        }
+    }
+}
+
+// Camera settings as a photographer would give them, with lengths in millimetres, the
+// shutter speed in seconds and the roll in radians. Scenes are taken to be in metres.
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalCamera {
+    pub focal_length: f32,
+    pub sensor_width: f32,
+    pub sensor_height: f32,
+    pub f_number: f32,
+    pub shutter: f32,
+    pub iso: f32,
+    pub roll: f32,
+}
+
+impl PhysicalCamera {
+    // A full frame sensor behind a 50mm lens, exposed by the sunny 16 rule.
+    pub fn new() -> Self {
+        Self {
+            focal_length: 50.0,
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            f_number: 16.0,
+            shutter: 1.0 / 100.0,
+            iso: 100.0,
+            roll: 0.0,
+        }
+    }
+
+    // The scale applied to scene radiance, normalised so that the sunny 16 rule of f/16 at
+    // a shutter speed of one over the ISO leaves it unchanged.
+    pub fn exposure(&self) -> f32 {
+        256.0 * self.shutter * self.iso / (self.f_number * self.f_number)
+    }
+}
+
+impl Default for PhysicalCamera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 pub struct Camera {
     focus_distance: f32,
@@ -13,6 +165,20 @@ pub struct Camera {
     w: f32,
     open: f32,
     close: f32,
+    shutter: Shutter,
+    aperture: Aperture,
+    cat_eye: f32,
+    projection: Projection,
+    stereo: Option<Stereo>,
+    exposure: f32,
+    shift: Vec2,
+    // The normal of the plane of sharp focus, when it is tilted.
+    focal_plane: Option<Vec3>,
+    // A lens system traced in place of the thin lens, with the height of the frame on the
+    // film and the distance from the film to the rear element.
+    lens: Option<Arc<Lens>>,
+    film_height: f32,
+    film_distance: f32,
 }
 
 impl Camera {
@@ -39,18 +205,354 @@ impl Camera {
             w,
             open,
             close,
+            shutter: Shutter::Global,
+            aperture: Aperture::Circle,
+            cat_eye: 0.0,
+            projection: Projection::Perspective,
+            stereo: None,
+            exposure: 1.0,
+            shift: Vec2::zero(),
+            focal_plane: None,
+            lens: None,
+            film_height: 0.0,
+            film_distance: 0.0,
         }
     }
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        let uvw = (Vec4::new(u, v, self.w, 0.0) * self.scaling).normalized() * self.focus_distance;
-        let rd = self.rotation * uvw;
+    // A camera for a frame of the given aspect ratio, fitted within the sensor.
+    pub fn physical(
+        position: Vec3,
+        look_at: Vec3,
+        aspect: f32,
+        focus_distance: f32,
+        settings: &PhysicalCamera,
+    ) -> Self {
+        let height = settings.sensor_height.min(settings.sensor_width / aspect);
 
-        let dof_offset = random_in_unit_disk() * self.lens_radius;
-        let offset = self.rotation * Vec4::new(dof_offset.x, dof_offset.y, 0.0, 0.0);
+        // The frame spans half of the field of view given to `new` on either side.
+        let fov = 2.0 * (height / settings.focal_length).atan();
+        let aperture = settings.focal_length / settings.f_number / 1000.0;
 
-        let time = thread_rng().gen_range(self.open, self.close);
-        Ray::new(self.position + offset.xyz(), rd.xyz() - offset.xyz(), time)
+        let mut camera = Self::new(
+            position,
+            look_at,
+            aspect,
+            fov,
+            focus_distance,
+            aperture,
+            0.0,
+            settings.shutter,
+        );
+        camera.rotation = camera.rotation * Mat4::from_rotation_z(settings.roll);
+        camera.exposure = settings.exposure();
+
+        camera
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) -> &mut Self {
+        self.projection = projection;
+        self
+    }
+
+    pub fn set_shutter(&mut self, shutter: Shutter) -> &mut Self {
+        self.shutter = shutter;
+        self
+    }
+
+    pub fn set_aperture(&mut self, aperture: Aperture) -> &mut Self {
+        self.aperture = aperture;
+        self
+    }
+
+    // Optical vignetting, where the lens barrel cuts off the opening towards the edges of
+    // the frame so that out of focus highlights there turn into cat's eyes.
+    pub fn set_cat_eye(&mut self, strength: f32) -> &mut Self {
+        self.cat_eye = strength.max(0.0);
+        self
+    }
+
+    pub fn set_stereo(&mut self, stereo: Stereo) -> &mut Self {
+        self.stereo = Some(stereo);
+        self
+    }
+
+    // Slides the frame across the lens by fractions of the frame height, so that verticals
+    // stay parallel when looking level at a tall building and shifting up to fit it in.
+    pub fn set_shift(&mut self, x: f32, y: f32) -> &mut Self {
+        self.shift = Vec2::new(x, y);
+        self
+    }
+
+    // Tilts the plane of sharp focus about the horizontal axis by `tilt` and about the
+    // vertical axis by `swing`, pivoting at the focus distance, as a tilted lens does by the
+    // Scheimpflug principle. A positive tilt leans the top of the plane away, lying it along
+    // a table top or the ground. Even without any tilt, focus then falls on this plane rather
+    // than on the sphere at the focus distance, so the edges of the frame focus further away.
+    pub fn set_tilt(&mut self, tilt: f32, swing: f32) -> &mut Self {
+        let right = Vec3::new(swing.cos(), 0.0, -swing.sin());
+        let up = Vec3::new(0.0, tilt.cos(), -tilt.sin());
+        self.focal_plane = Some(right.cross(up).normalized());
+        self
+    }
+
+    // Traces rays through a lens system instead, from a frame of the given height in
+    // millimetres. The lens brings its own distortion, vignetting and depth of field, so
+    // the projection, stereo and aperture settings no longer apply.
+    pub fn set_lens(&mut self, lens: Arc<Lens>, film_height: f32) -> &mut Self {
+        self.film_distance = lens.focus(self.focus_distance);
+        self.film_height = film_height / 1000.0;
+        self.lens = Some(lens);
+        self
+    }
+
+    pub fn focus_on(&mut self, point: Vec3) -> &mut Self {
+        self.focus_distance = (point - self.position).mag();
+        self.refocus()
+    }
+
+    // Focuses on the near side of an object from a point inside of it, such as its centroid,
+    // by looking for the first surface between the camera and that point.
+    pub fn focus_on_object(&mut self, world: &World, point: Vec3) -> &mut Self {
+        let ray = Ray::new(self.position, point - self.position, self.open);
+
+        match world.hit_surface(&ray, 0.001, f32::INFINITY) {
+            Some(hit) => self.focus_on(hit.point),
+            None => self.focus_on(point),
+        }
+    }
+
+    // Focuses on whatever is seen through the center of the lens at a point on the frame,
+    // leaving the focus alone if nothing is there. Participating media are looked through, as
+    // they have no surface to focus on.
+    pub fn autofocus(&mut self, world: &World, u: f32, v: f32) -> &mut Self {
+        let ray = match self.ray(u, v, self.open, false) {
+            Some(ray) => ray,
+            None => return self,
+        };
+
+        if let Some(hit) = world.hit_surface(&ray, 0.001, f32::INFINITY) {
+            // A lens system focuses at distances from the film rather than from its front.
+            self.focus_distance = match self.lens {
+                Some(_) => (hit.point - self.position).mag(),
+                None => hit.t * ray.direction().mag(),
+            };
+        }
+
+        self.refocus()
+    }
+
+    fn refocus(&mut self) -> &mut Self {
+        if let Some(lens) = &self.lens {
+            self.film_distance = lens.focus(self.focus_distance);
+        }
+
+        self
+    }
+
+    // The ray through a point on the frame, unless the lens system blocks it.
+    pub fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        let delay = match self.shutter {
+            Shutter::Global => 0.0,
+            Shutter::Rolling(readout) => (0.5 - v) * readout,
+        };
+        let time = thread_rng().gen_range(self.open, self.close) + delay;
+
+        self.ray(u, v, time, true)
+    }
+
+    // The ray through a point on the frame at the given time, through a random point of the
+    // aperture or, without jitter, through its center.
+    fn ray(&self, u: f32, v: f32, time: f32, jitter: bool) -> Option<Ray> {
+        let aspect = self.scaling.x;
+        let to_world = |v: Vec3| (self.rotation * Vec4::new(v.x, v.y, v.z, 0.0)).xyz();
+
+        if let Some(lens) = &self.lens {
+            // The lens flips the image over onto the film, and looks along +z in its own
+            // space, which is turned around from the camera's.
+            let film = Vec3::new(u * aspect, -v, 0.0) * self.film_height;
+            let (o, d) = match jitter {
+                true => lens.sample_ray(film, self.film_distance)?,
+                false => lens.chief_ray(film, self.film_distance)?,
+            };
+            let flip = |v: Vec3| Vec3::new(-v.x, v.y, -v.z);
+
+            return Some(Ray::new(
+                self.position + to_world(flip(o)),
+                to_world(flip(d)),
+                time,
+            ));
+        }
+
+        // Each eye gets half of the frame, remapped to the full range of its own view.
+        let (u, v, aspect, eye) = match self.stereo.map(|s| s.layout) {
+            Some(StereoLayout::SideBySide) => match u < 0.0 {
+                true => ((u + 0.25) * 2.0, v, aspect / 2.0, -1.0),
+                false => ((u - 0.25) * 2.0, v, aspect / 2.0, 1.0),
+            },
+            Some(StereoLayout::TopBottom) => match v > 0.0 {
+                true => (u, (v - 0.25) * 2.0, aspect * 2.0, -1.0),
+                false => (u, (v + 0.25) * 2.0, aspect * 2.0, 1.0),
+            },
+            None => (u, v, aspect, 0.0),
+        };
+
+        let (origin, direction) = self.project(u, v, aspect);
+        let (origin, direction) = match self.stereo {
+            Some(stereo) => self.offset_eye(&stereo, eye, origin, direction),
+            None => (origin, direction),
+        };
+        let focus = self.focus(origin, direction);
+
+        // Panoramic views look every way, so their lens faces along each ray.
+        let (lens_x, lens_y) = match self.projection {
+            Projection::Perspective | Projection::Orthographic(_) => {
+                (Vec3::unit_x(), Vec3::unit_y())
+            }
+            _ => orthonormal_basis(direction),
+        };
+
+        let dof_offset = match jitter {
+            true => self.sample_lens(u, v, aspect) * self.lens_radius,
+            false => Vec3::zero(),
+        };
+        let lens = origin + dof_offset.x * lens_x + dof_offset.y * lens_y;
+
+        Some(Ray::new(
+            self.position + to_world(lens),
+            to_world(focus - lens),
+            time,
+        ))
+    }
+
+    // Where a ray through the center of the lens is in focus, on the tilted focal plane if
+    // there is one and it lies ahead, and otherwise on the sphere at the focus distance.
+    fn focus(&self, origin: Vec3, direction: Vec3) -> Vec3 {
+        let sphere = origin + direction * self.focus_distance;
+
+        let normal = match self.focal_plane {
+            Some(normal) => normal,
+            None => return sphere,
+        };
+
+        let pivot = Vec3::new(0.0, 0.0, -self.focus_distance);
+        let facing = direction.dot(normal);
+
+        if facing.abs() < 1e-6 {
+            return sphere;
+        }
+
+        match (pivot - origin).dot(normal) / facing {
+            t if t > 0.0 => origin + direction * t,
+            _ => sphere,
+        }
+    }
+
+    // The origin and unit direction of the ray through a point on the frame, relative to
+    // the camera looking down -z.
+    fn project(&self, u: f32, v: f32, aspect: f32) -> (Vec3, Vec3) {
+        match self.projection {
+            Projection::Perspective => {
+                let uvw = Vec3::new(u * aspect + self.shift.x, v + self.shift.y, self.w);
+                (Vec3::zero(), uvw.normalized())
+            }
+            Projection::Orthographic(height) => {
+                let (x, y) = (u * aspect + self.shift.x, v + self.shift.y);
+                let origin = Vec3::new(x * height, y * height, 0.0);
+                (origin, -Vec3::unit_z())
+            }
+            Projection::Fisheye(fov) => {
+                let (x, y) = (2.0 * u * aspect, 2.0 * v);
+                let r = (x * x + y * y).sqrt();
+
+                if r < 1e-6 {
+                    return (Vec3::zero(), -Vec3::unit_z());
+                }
+
+                // The angle from the view direction grows in step with the distance from
+                // the center of the frame.
+                let (sin, cos) = (r * fov / 2.0).sin_cos();
+                (Vec3::zero(), Vec3::new(sin * x / r, sin * y / r, -cos))
+            }
+            Projection::Equirectangular => {
+                let (longitude, latitude) = (u * 2.0 * PI, v * PI);
+                let direction = Vec3::new(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    -latitude.cos() * longitude.cos(),
+                );
+                (Vec3::zero(), direction)
+            }
+            Projection::Cubemap => {
+                let (s, t) = ((u + 0.5) * 3.0, (0.5 - v) * 2.0);
+                let (column, row) = (s.floor().max(0.0).min(2.0), t.floor().max(0.0).min(1.0));
+                let (a, b) = ((s - column) * 2.0 - 1.0, 1.0 - (t - row) * 2.0);
+
+                // The forward, right and up axes of each face.
+                let (x, y, z) = (Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z());
+                let (forward, right, up) = match (row as u32) * 3 + column as u32 {
+                    0 => (x, -z, y),
+                    1 => (-x, z, y),
+                    2 => (y, x, -z),
+                    3 => (-y, x, z),
+                    4 => (z, x, y),
+                    _ => (-z, -x, y),
+                };
+
+                (Vec3::zero(), (forward + a * right + b * up).normalized())
+            }
+        }
+    }
+
+    // Moves the ray from the center of the head to the left (-1) or right (1) eye, aimed
+    // at the point the center ray reaches at the convergence distance.
+    fn offset_eye(&self, stereo: &Stereo, eye: f32, origin: Vec3, direction: Vec3) -> (Vec3, Vec3) {
+        let half = eye * stereo.interocular / 2.0;
+
+        let (offset, converged) = match self.projection {
+            Projection::Perspective | Projection::Orthographic(_) => (
+                Vec3::new(half, 0.0, 0.0),
+                origin + direction * (stereo.convergence / -direction.z.min(-1e-6)),
+            ),
+            // The eyes turn with the head to face each direction, so they stay level and
+            // at right angles to the ray.
+            Projection::Equirectangular => {
+                let side = Vec3::new(-direction.z, 0.0, direction.x);
+                let side = match side.mag_sq() > 1e-12 {
+                    true => side.normalized(),
+                    false => Vec3::unit_x(),
+                };
+                (side * half, origin + direction * stereo.convergence)
+            }
+            _ => (
+                Vec3::new(half, 0.0, 0.0),
+                origin + direction * stereo.convergence,
+            ),
+        };
+
+        let origin = origin + offset;
+        (origin, (converged - origin).normalized())
+    }
+
+    // The barrel is a second opening shifted away from the frame's center, and only points
+    // within both let light through.
+    fn sample_lens(&self, u: f32, v: f32, aspect: f32) -> Vec3 {
+        let shift = Vec3::new(u * aspect, v, 0.0) * 2.0 * self.cat_eye;
+        let shift = match shift.mag() > 0.9 {
+            true => shift.normalized() * 0.9,
+            false => shift,
+        };
+
+        loop {
+            let p = self.aperture.sample();
+
+            if (p - shift).mag() <= 1.0 {
+                return p;
+            }
+        }
     }
 }
 
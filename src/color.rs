@@ -8,13 +8,18 @@ impl Color {
         Color(r, g, b)
     }
 
-    pub fn from_samples(samples: Vec<Vec3>) -> Self {
-        let summed: Vec3 = samples.iter().fold(Vec3::zero(), |acc, s| *s + acc);
-        let scale = 1.0 / samples.len() as f32;
-
-        let r = (summed.x * scale).sqrt().max(0.0).min(1.0);
-        let g = (summed.y * scale).sqrt().max(0.0).min(1.0);
-        let b = (summed.z * scale).sqrt().max(0.0).min(1.0);
+    // The color of a weighted sum of samples, which is black until the weights add up to
+    // something positive.
+    pub fn from_weighted(summed: Vec3, weight: f32) -> Self {
+        if weight <= 0.0 {
+            return Self::black();
+        }
+
+        let scale = 1.0 / weight;
+
+        let r = (summed.x * scale).max(0.0).sqrt().min(1.0);
+        let g = (summed.y * scale).max(0.0).sqrt().min(1.0);
+        let b = (summed.z * scale).max(0.0).sqrt().min(1.0);
 
         Vec3::new(r, g, b).into()
     }
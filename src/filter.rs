@@ -0,0 +1,75 @@
+use std::f32::consts::PI;
+
+// Reconstruction filters weighing how much each sample counts towards the pixels around
+// it, all separable in x and y and taking their radius in pixels first.
+#[derive(Clone, Copy, Debug)]
+pub enum Filter {
+    Box(f32),
+    Tent(f32),
+    // Radius and falloff.
+    Gaussian(f32, f32),
+    // Radius and the B and C parameters, with 1/3 each being the recommended balance
+    // between blurring and ringing.
+    Mitchell(f32, f32, f32),
+    Lanczos(f32),
+}
+
+impl Filter {
+    pub fn radius(&self) -> f32 {
+        match self {
+            Filter::Box(radius)
+            | Filter::Tent(radius)
+            | Filter::Gaussian(radius, _)
+            | Filter::Mitchell(radius, ..)
+            | Filter::Lanczos(radius) => *radius,
+        }
+    }
+
+    // The weight of a sample at an offset from a pixel's center, which may be negative for
+    // the sharpening filters.
+    pub fn weight(&self, x: f32, y: f32) -> f32 {
+        self.weight_1d(x) * self.weight_1d(y)
+    }
+
+    fn weight_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        let radius = self.radius();
+
+        if x > radius {
+            return 0.0;
+        }
+
+        match self {
+            Filter::Box(_) => 1.0,
+            Filter::Tent(_) => radius - x,
+            Filter::Gaussian(_, falloff) => {
+                ((-falloff * x * x).exp() - (-falloff * radius * radius).exp()).max(0.0)
+            }
+            Filter::Mitchell(_, b, c) => {
+                let x = 2.0 * x / radius;
+                let weight = match x < 1.0 {
+                    true => {
+                        (12.0 - 9.0 * b - 6.0 * c) * x.powi(3)
+                            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                            + (6.0 - 2.0 * b)
+                    }
+                    false => {
+                        (-b - 6.0 * c) * x.powi(3)
+                            + (6.0 * b + 30.0 * c) * x * x
+                            + (-12.0 * b - 48.0 * c) * x
+                            + (8.0 * b + 24.0 * c)
+                    }
+                };
+                weight / 6.0
+            }
+            Filter::Lanczos(_) => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    match x.abs() < 1e-5 {
+        true => 1.0,
+        false => (PI * x).sin() / (PI * x),
+    }
+}
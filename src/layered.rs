@@ -0,0 +1,89 @@
+use rand::random;
+use ultraviolet::Vec3;
+
+use crate::materials::{reflect, Material, Reflection, Scatter, Texture};
+use crate::microfacet::{fresnel_dielectric, sample_visible_normal, smith_g1, Frame};
+use crate::{Hit, Ray};
+
+// A thin clear dielectric layer on top of another material, like lacquer or varnish. The
+// tint is the color transmitted straight through a coat of unit thickness.
+#[derive(Clone)]
+pub struct Coat {
+    pub ior: f32,
+    pub roughness: f32,
+    pub tint: Texture,
+    pub thickness: f32,
+}
+
+impl Coat {
+    pub fn new(ior: f32, roughness: f32, tint: Texture, thickness: f32) -> Self {
+        Self {
+            ior,
+            roughness,
+            tint,
+            thickness,
+        }
+    }
+
+    pub fn scatter(&self, base: &Material, ray: &Ray, hit: &Hit) -> Option<Reflection> {
+        let frame = Frame::from_hit(hit);
+        let wo = frame.to_local(-ray.direction().normalized());
+
+        if wo.z <= 0.0 {
+            return base.scatter(ray, hit);
+        }
+
+        let eta_ratio = hit.incident_ior / self.ior;
+        let alpha = (self.roughness * self.roughness).max(0.001);
+        let m = sample_visible_normal(wo, alpha, alpha);
+
+        // Reflect off the coat with the Fresnel probability, otherwise pass through it to
+        // the base and back out again.
+        if random::<f32>() < fresnel_dielectric(wo.dot(m), eta_ratio) {
+            let wi = reflect(-wo, m);
+
+            if wi.z <= 0.0 {
+                return None;
+            }
+
+            let reflection = Reflection {
+                attenuation: Vec3::broadcast(smith_g1(wi, alpha, alpha)),
+                scatter: Ray::new(hit.point, frame.to_world(wi), ray.time()),
+                spectral: false,
+                pass_through: false,
+            };
+
+            return Some(reflection);
+        }
+
+        let mut reflection = base.scatter(ray, hit)?;
+
+        // Path length through the coat relative to its thickness, for a ray leaving the
+        // surface with the given cosine.
+        let slant = |cosine: f32| {
+            let sin_sq = (1.0 - cosine * cosine) * eta_ratio * eta_ratio;
+            1.0 / (1.0 - sin_sq).max(0.0001).sqrt()
+        };
+
+        let cos_i = reflection.scatter.direction().normalized().dot(hit.normal);
+        let (path, exit) = match cos_i > 0.0 {
+            true => (
+                slant(wo.z) + slant(cos_i),
+                // The same by reciprocity as leaving the coat at the refracted angle inside.
+                1.0 - fresnel_dielectric(cos_i, eta_ratio),
+            ),
+            false => (slant(wo.z), 1.0),
+        };
+
+        let tint = self.tint.value(hit);
+        let absorbed = tint.map(|c| c.max(0.0001).powf(self.thickness * path));
+        let absorbed = match reflection.spectral {
+            true => ray.spectral(absorbed),
+            false => absorbed,
+        };
+
+        reflection.attenuation = reflection.attenuation * absorbed * exit;
+
+        Some(reflection)
+    }
+}
@@ -0,0 +1,266 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rand::prelude::*;
+use ultraviolet::Vec3;
+
+use crate::materials::refract;
+
+// One surface of a lens prescription. A radius of zero marks the flat aperture stop.
+#[derive(Clone, Copy, Debug)]
+pub struct Interface {
+    pub radius: f32,
+    pub thickness: f32,
+    pub ior: f32,
+    pub aperture: f32,
+}
+
+// A system of spherical lens elements, traced in its own space with the film at z = 0 and
+// the scene towards +z. Interfaces are listed from the front of the lens to the back, each
+// with the distance to the next one, or to the film for the last one.
+pub struct Lens {
+    interfaces: Vec<Interface>,
+}
+
+impl Lens {
+    // A lens needs at least one interface, the last of which sits in front of the film.
+    pub fn new(interfaces: Vec<Interface>) -> Option<Self> {
+        match interfaces.is_empty() {
+            true => None,
+            false => Some(Self { interfaces }),
+        }
+    }
+
+    // Reads a prescription in the format used by pbrt, with a line of radius, thickness,
+    // index of refraction and aperture diameter in millimetres per interface. Lines starting
+    // with `#` are comments, and an index of zero means air.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut interfaces = Vec::new();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(|v| {
+                    v.parse::<f32>()
+                        .map_err(|_| invalid("lens value is malformed"))
+                })
+                .collect::<io::Result<Vec<f32>>>()?;
+
+            if values.len() != 4 {
+                return Err(invalid("lens interfaces need four values"));
+            }
+
+            interfaces.push(Interface {
+                radius: values[0] / 1000.0,
+                thickness: values[1] / 1000.0,
+                ior: match values[2] {
+                    0.0 => 1.0,
+                    ior => ior,
+                },
+                aperture: values[3] / 2000.0,
+            });
+        }
+
+        Self::new(interfaces).ok_or_else(|| invalid("lens has no interfaces"))
+    }
+
+    // Traces a ray from a point on the film out through a random point on the rear element,
+    // returning the ray leaving the front of the lens unless it is blocked along the way.
+    pub fn sample_ray(&self, film: Vec3, film_distance: f32) -> Option<(Vec3, Vec3)> {
+        let rear = self.interfaces.last()?;
+        let p = loop {
+            let mut rng = thread_rng();
+            let (x, y) = (rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0));
+
+            if x * x + y * y < 1.0 {
+                break Vec3::new(x * rear.aperture, y * rear.aperture, film_distance);
+            }
+        };
+
+        self.trace(film, (p - film).normalized(), film_distance, true)
+    }
+
+    // Traces the ray from a point on the film through the center of the rear element, free
+    // of the blur of the rest of the aperture.
+    pub fn chief_ray(&self, film: Vec3, film_distance: f32) -> Option<(Vec3, Vec3)> {
+        let p = Vec3::new(0.0, 0.0, film_distance);
+        self.trace(film, (p - film).normalized(), film_distance, true)
+    }
+
+    // The distance from the film to the rear element which brings objects at the given
+    // distance from the film into focus, by the thick lens approximation.
+    pub fn focus(&self, distance: f32) -> f32 {
+        let nominal = self.interfaces.last().map_or(0.0, |i| i.thickness);
+
+        let (front, rear) = match (
+            self.cardinal_points(nominal, true),
+            self.cardinal_points(nominal, false),
+        ) {
+            (Some(front), Some(rear)) => (front, rear),
+            _ => return nominal,
+        };
+
+        // Principal planes and focal points, as (principal, focal) pairs along z.
+        let focal_length = rear.0 - rear.1;
+        let (a, b) = (distance - front.0, rear.0);
+
+        let discriminant = (a + b) * (a + b - 4.0 * focal_length);
+        let shift = match discriminant >= 0.0 {
+            true => ((a - b) - discriminant.sqrt()) / 2.0,
+            false => -rear.1,
+        };
+
+        nominal + shift
+    }
+
+    // Traces a ray parallel to the axis from beyond either end of the lens, finding the
+    // principal plane where the ray appears to bend and the focal point where it meets the
+    // axis on the other side.
+    fn cardinal_points(&self, film_distance: f32, from_film: bool) -> Option<(f32, f32)> {
+        let front = self.positions(film_distance)[0];
+        let height = self
+            .interfaces
+            .iter()
+            .map(|i| i.aperture)
+            .fold(f32::INFINITY, f32::min)
+            * 0.01;
+
+        let (origin, direction) = match from_film {
+            true => (Vec3::new(height, 0.0, -1.0), Vec3::unit_z()),
+            false => (Vec3::new(height, 0.0, front + 1.0), -Vec3::unit_z()),
+        };
+
+        let (o, d) = self.trace(origin, direction, film_distance, from_film)?;
+
+        if d.x.abs() < 1e-9 {
+            return None;
+        }
+
+        let principal = o.z + (height - o.x) / d.x * d.z;
+        let focal = o.z - o.x / d.x * d.z;
+
+        Some((principal, focal))
+    }
+
+    // Refracts a ray through each interface in turn, towards the scene or towards the film.
+    fn trace(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        film_distance: f32,
+        towards_scene: bool,
+    ) -> Option<(Vec3, Vec3)> {
+        let count = self.interfaces.len();
+        let positions = self.positions(film_distance);
+
+        let order: Vec<usize> = match towards_scene {
+            true => (0..count).rev().collect(),
+            false => (0..count).collect(),
+        };
+
+        let (mut o, mut d) = (origin, direction);
+
+        for i in order {
+            let interface = &self.interfaces[i];
+            let z = positions[i];
+
+            let (t, normal) = match interface.radius == 0.0 {
+                true => {
+                    if d.z.abs() < 1e-9 {
+                        return None;
+                    }
+                    ((z - o.z) / d.z, -Vec3::unit_z())
+                }
+                false => intersect_sphere(o, d, z, interface.radius)?,
+            };
+
+            let p = o + d * t;
+            if p.x * p.x + p.y * p.y > interface.aperture * interface.aperture {
+                return None;
+            }
+
+            o = p;
+
+            if interface.radius == 0.0 {
+                continue;
+            }
+
+            // The glass behind each interface is given by its own index, and in front of it
+            // by the index of the one before.
+            let behind = interface.ior;
+            let front = match i {
+                0 => 1.0,
+                _ => self.interfaces[i - 1].ior,
+            };
+            let eta_ratio = match towards_scene {
+                true => behind / front,
+                false => front / behind,
+            };
+
+            let normal = match normal.dot(d) > 0.0 {
+                true => -normal,
+                false => normal,
+            };
+
+            let cos_theta = -d.dot(normal);
+            if eta_ratio * eta_ratio * (1.0 - cos_theta * cos_theta) > 1.0 {
+                return None;
+            }
+
+            d = refract(d, normal, eta_ratio).normalized();
+        }
+
+        Some((o, d))
+    }
+
+    // Where each interface sits along the axis, counting forward from the film.
+    fn positions(&self, film_distance: f32) -> Vec<f32> {
+        let mut positions = vec![film_distance; self.interfaces.len()];
+
+        for i in (0..self.interfaces.len() - 1).rev() {
+            positions[i] = positions[i + 1] + self.interfaces[i].thickness;
+        }
+
+        positions
+    }
+}
+
+// Meets the sphere of an interface at `z` on the axis, with a positive radius curving its
+// center back towards the film. Returns the distance along the ray and the outward normal.
+fn intersect_sphere(origin: Vec3, direction: Vec3, z: f32, radius: f32) -> Option<(f32, Vec3)> {
+    let center = Vec3::new(0.0, 0.0, z - radius);
+    let oc = origin - center;
+
+    let a = direction.mag_sq();
+    let half_b = oc.dot(direction);
+    let c = oc.mag_sq() - radius * radius;
+
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let root = discriminant.sqrt();
+    let (near, far) = ((-half_b - root) / a, (-half_b + root) / a);
+
+    // Only one side of the sphere is the lens surface, which depends on the way it curves
+    // and the way the ray is going.
+    let t = match (direction.z > 0.0) ^ (radius < 0.0) {
+        true => far,
+        false => near,
+    };
+
+    if t < 0.0 {
+        return None;
+    }
+
+    Some((t, (origin + direction * t - center) / radius.abs()))
+}
@@ -1,3 +1,4 @@
+use std::f32::consts::PI;
 use std::sync::mpsc;
 use std::thread;
 use std::time::Instant;
@@ -5,23 +6,39 @@ use std::time::Instant;
 use minifb::{Key, ScaleMode, Window, WindowOptions};
 use rand::{prelude::*, seq::SliceRandom};
 use rayon::prelude::*;
-use ultraviolet::Vec3;
+use ultraviolet::{Vec2, Vec3};
 
 mod camera;
 mod color;
+mod filter;
+mod layered;
+mod lens;
 mod materials;
 mod matrix;
+mod medium;
+mod merl;
+mod microfacet;
 mod pixmap;
+mod principled;
 mod ray;
 mod shapes;
+mod spectrum;
+mod volumes;
 mod world;
 
-use camera::Camera;
+use camera::{Aperture, Camera, PhysicalCamera, Projection, Shutter, Stereo, StereoLayout};
 use color::Color;
-use materials::{Material, PerlinNoise, Texture};
+use filter::Filter;
+use layered::Coat;
+use lens::Lens;
+use materials::{orthonormal_basis, Material, Octaves, PerlinNoise, Subsurface, Texture, ThinFilm};
+use merl::MerlBrdf;
 use pixmap::PixMap;
+use principled::Principled;
 use ray::Ray;
-use shapes::Sphere;
+use shapes::{Quad, Sphere};
+use spectrum::Ior;
+use volumes::{ConstantMedium, Density, HeterogeneousMedium, VoxelGrid};
 use world::World;
 
 fn main() {
@@ -34,46 +51,189 @@ fn main() {
 
     let _red = Texture::solid(255, 255, 255);
     let _green = Texture::solid(20, 20, 20);
-    let _grey = Texture::Checker(Box::new(_red.clone()), Box::new(_green.clone()));
-    let _noise = Texture::Perlin(PerlinNoise::new(256));
+    let _grey = Texture::checker(_red.clone(), _green.clone(), 10.0);
+    let _noise = Texture::Marble(PerlinNoise::new(256), 4.0).ramp(vec![
+        (0.0, Vec3::new(0.1, 0.1, 0.12)),
+        (0.6, Vec3::new(0.6, 0.55, 0.5)),
+        (1.0, Vec3::new(0.95, 0.95, 0.9)),
+    ]);
 
     let noise = Material::Lambertian(_noise);
     let red = Material::Lambertian(_red.clone());
-    let green = Material::Lambertian(_green);
-    let mirror = Material::Metal(_red.clone(), 0.0);
+    let _bumps = Texture::Fbm(PerlinNoise::new(256), 6.0, Octaves::default());
+    let bumpy_red = Material::Bump(Box::new(red.clone()), _bumps, 0.02);
+    let varnish = Coat::new(1.5, 0.05, Texture::solid(250, 235, 200), 0.5);
+    let varnished_red = Material::Layered(Box::new(bumpy_red), varnish);
+    let mut lacquer = Principled::new(_green);
+    lacquer.roughness = Texture::constant(0.3);
+    lacquer.clearcoat = Texture::constant(1.0);
+    let green = Material::Principled(Box::new(lacquer));
+    let _mirror = Material::Metal(_red.clone(), 0.0);
+    let brushed = Material::Anisotropic(_red.clone(), 0.05, 0.4, None);
     let blur_mirror = Material::Metal(_grey.clone(), 0.3);
-    let glass = Material::Dielectric(Vec3::new(1.0, 1.0, 1.0), 1.55);
-    let amber = Material::Dielectric(Vec3::new(1.0, 0.8, 0.88), 1.31);
+    let glass = Material::Dielectric(Vec3::zero(), Ior::Cauchy(1.5, 0.017), 1, None);
+    let air = Material::Dielectric(Vec3::zero(), Ior::Constant(1.0), 2, None);
+    let amber = Material::Dielectric(Vec3::new(0.0, 0.25, 0.14), Ior::Constant(1.31), 3, None);
+    let soap = ThinFilm::new(1.33, 500.0, Texture::Perlin(PerlinNoise::new(256), 2.0));
+    let bubble = Material::Dielectric(Vec3::zero(), Ior::Constant(1.0), 4, Some(soap));
+    let candle = Subsurface::new(Vec3::new(0.99, 0.9, 0.7), Vec3::broadcast(0.1), 0.0, 1.4);
+    let wax = Material::Subsurface(candle);
+    let tile_mask = Texture::checker(Texture::constant(0.0), Texture::constant(1.0), 4.0 * PI);
+    let tiles = Material::Mix(Box::new(red.clone()), Box::new(_mirror), tile_mask.uv());
+    let fence_mask = Texture::checker(Texture::constant(0.0), Texture::constant(1.0), 12.0 * PI);
+    let fence = Material::Cutout(Box::new(green.clone()), fence_mask.uv());
+    let pane = Material::ThinDielectric(Texture::solid(235, 250, 240), 1.52);
+    let smoke = Material::HenyeyGreenstein(_red.clone(), 0.6);
+    let fire = Material::Emissive(Texture::solid(255, 140, 40), 4.0);
 
     let s1 = Sphere::new(Vec3::new(0.0, -100.5, 0.0), 100.0, blur_mirror.clone());
-    let s2 = Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0, mirror.clone());
-    let s3 = Sphere::new(Vec3::new(1.0, 0.2, 2.0), 0.4, red.clone());
+    let s2 = Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0, brushed.clone());
+    let s3 = Sphere::new(Vec3::new(1.0, 0.2, 2.0), 0.4, varnished_red.clone());
     let s4 = Sphere::new(Vec3::new(-2.0, 0.5, 0.0), 0.7, glass.clone());
-    let s5 = Sphere::new(Vec3::new(-2.0, 0.5, 0.0), -0.6, glass.clone());
+    let s5 = Sphere::new(Vec3::new(-2.0, 0.5, 0.0), 0.6, air.clone());
     let s6 = Sphere::new(Vec3::new(-2.0, 0.5, 0.0), 0.45, amber.clone());
-    let s7 = Sphere::new(Vec3::new(-0.9, 1.1, -7.0), 0.4, green.clone());
+    // Racing across the frame, so that the rolling shutter leans it over as each row further
+    // down sees it a little further along.
+    let mut s7 = Sphere::new(Vec3::new(-1.45, 1.1, -7.0), 0.4, green.clone());
+    s7.set_velocity(Vec3::new(60.0, 0.0, 0.0));
+    let b8 = Sphere::new(Vec3::new(0.8, 0.4, -3.0), 0.8, Material::default());
+    let s8 = ConstantMedium::new(Box::new(b8), 1.5, smoke.clone());
+    let b9 = Sphere::new(Vec3::new(-3.5, 0.6, -4.0), 1.0, Material::default());
+    let d9 = Density::Noise(PerlinNoise::new(256), 2.0, 6.0);
+    let mut s9 = HeterogeneousMedium::new(Box::new(b9), d9, smoke.clone());
+    s9.set_emission(fire.clone(), 0.3);
+    let s10 = Sphere::new(Vec3::new(0.2, 1.3, 4.0), 0.4, bubble.clone());
+    let s11 = Sphere::new(Vec3::new(-0.6, 0.3, 4.0), 0.5, wax.clone());
+    let q1 = Quad::new(
+        Vec3::new(0.8, -0.5, -6.0),
+        Vec3::new(2.5, 0.0, 0.5),
+        Vec3::new(0.0, 2.5, 0.0),
+        tiles.clone(),
+    );
+    let q2 = Quad::new(
+        Vec3::new(-4.5, -0.5, -1.5),
+        Vec3::new(1.8, 0.0, 0.0),
+        Vec3::new(0.0, 0.9, 0.0),
+        fence.clone(),
+    );
+    let q3 = Quad::new(
+        Vec3::new(0.4, -0.5, 3.2),
+        Vec3::new(1.4, 0.0, -0.3),
+        Vec3::new(0.0, 1.4, 0.0),
+        pane.clone(),
+    );
 
     let mut world = World::new();
     world
         .add_object(Box::new(s1))
         .add_object(Box::new(s2))
         .add_object(Box::new(s3))
-        .add_object(Box::new(s4))
+        .add_named_object("shell", Box::new(s4))
         .add_object(Box::new(s5))
         .add_object(Box::new(s6))
-        .add_object(Box::new(s7));
+        .add_object(Box::new(s7))
+        .add_object(Box::new(s8))
+        .add_object(Box::new(s9))
+        .add_object(Box::new(s10))
+        .add_object(Box::new(s11))
+        .add_object(Box::new(q1))
+        .add_object(Box::new(q2))
+        .add_object(Box::new(q3));
+
+    if let Ok(brdf) = MerlBrdf::load("measured.binary") {
+        let measured = Material::Measured(std::sync::Arc::new(brdf));
+        world.add_object(Box::new(Sphere::new(
+            Vec3::new(-1.3, 0.0, 2.2),
+            0.5,
+            measured,
+        )));
+    }
 
-    let camera = Camera::new(
+    let (cloud_min, cloud_max) = (Vec3::new(-6.0, 2.0, -12.0), Vec3::new(2.0, 4.0, -8.0));
+    if let Ok(grid) = VoxelGrid::load("cloud.vox", cloud_min, cloud_max) {
+        let boundary = Sphere::new((cloud_min + cloud_max) / 2.0, 4.5, Material::default());
+        let cloud = HeterogeneousMedium::new(
+            Box::new(boundary),
+            Density::Grid(std::sync::Arc::new(grid)),
+            smoke.clone(),
+        );
+        world.add_object(Box::new(cloud));
+    }
+
+    let settings = PhysicalCamera {
+        focal_length: 80.0,
+        f_number: 2.0,
+        shutter: 1.0 / 6400.0,
+        ..PhysicalCamera::new()
+    };
+    let mut camera = Camera::physical(
         Vec3::new(0.0, 2.0, 10.0),
         Vec3::new(-0.9, 0.9, 0.0),
         pm.aspect_ratio(),
-        1.0 / 2.0,
         10.0,
-        0.05,
-        0.0,
-        0.8,
+        &settings,
     );
 
+    camera
+        .set_shift(0.0, 0.05)
+        .set_tilt(0.1, 0.0)
+        .set_shutter(Shutter::Rolling(1.0 / 30.0));
+
+    // Focus on the glass shell, or on whatever is in the middle of the frame without it.
+    match world.centroid("shell") {
+        Some(point) => camera.focus_on_object(&world, point),
+        None => camera.autofocus(&world, 0.0, 0.0),
+    };
+    camera
+        .set_aperture(Aperture::Polygon(6, 0.2))
+        .set_cat_eye(0.4);
+    // The projection may be picked by name, optionally followed by a stereo layout, such as
+    // `raytracer equirectangular top-bottom`.
+    let args = std::env::args().collect::<Vec<String>>();
+    let projection = match args.get(1).map(String::as_str) {
+        Some("orthographic") => Projection::Orthographic(6.0),
+        Some("fisheye") => Projection::Fisheye(PI),
+        Some("equirectangular") => Projection::Equirectangular,
+        Some("cubemap") => Projection::Cubemap,
+        _ => Projection::Perspective,
+    };
+    camera.set_projection(projection);
+
+    let layout = match args.get(2).map(String::as_str) {
+        Some("side-by-side") => Some(StereoLayout::SideBySide),
+        Some("top-bottom") => Some(StereoLayout::TopBottom),
+        _ => None,
+    };
+    if let Some(layout) = layout {
+        camera.set_stereo(Stereo::new(0.065, 10.0, layout));
+    }
+
+    // A reconstruction filter may be named anywhere among the arguments.
+    let filter = args
+        .iter()
+        .find_map(|arg| match arg.as_str() {
+            "box" => Some(Filter::Box(0.5)),
+            "tent" => Some(Filter::Tent(1.0)),
+            "gaussian" => Some(Filter::Gaussian(1.5, 2.0)),
+            "lanczos" => Some(Filter::Lanczos(3.0)),
+            _ => None,
+        })
+        .unwrap_or(Filter::Mitchell(2.0, 1.0 / 3.0, 1.0 / 3.0));
+
+    // As may `spectral`, to trace each sample at its own wavelengths.
+    let spectral = args.iter().any(|arg| arg == "spectral");
+
+    if let Ok(lens) = Lens::load("lens.dat") {
+        let height = settings
+            .sensor_height
+            .min(settings.sensor_width / pm.aspect_ratio());
+        camera.set_lens(std::sync::Arc::new(lens), height);
+    }
+
+    if let Ok(mask) = PixMap::load("aperture.ppm") {
+        camera.set_aperture(Aperture::Image(std::sync::Arc::new(mask)));
+    }
+
     let mut options = WindowOptions::default();
     options.resize = true;
     options.scale_mode = ScaleMode::Center;
@@ -93,37 +253,67 @@ fn main() {
     let (tx, rx) = mpsc::channel();
 
     thread::spawn(move || {
-        pixels.into_par_iter().for_each_with(tx, |tx, (x, y)| {
-            let mut samples = Vec::new();
+        // Each pixel's samples reach as far as the filter does into its neighbours, which
+        // are gathered up here before being sent off.
+        let reach = (filter.radius() + 0.5).ceil() as i32;
+        let side = 2 * reach + 1;
 
-            for s in 0..aa_samples {
-                let mut sample_i = x as f32;
-                let mut sample_j = y as f32;
+        pixels.into_par_iter().for_each_with(tx, |tx, (x, y)| {
+            let mut splats = vec![(Vec3::zero(), 0.0); (side * side) as usize];
 
-                if s > 0 {
-                    sample_i += random::<f32>() - 0.5;
-                    sample_j += random::<f32>() - 0.5;
-                }
+            for _ in 0..aa_samples {
+                let sample_i = x as f32 + random::<f32>() - 0.5;
+                let sample_j = y as f32 + random::<f32>() - 0.5;
 
                 // UV coordinates are on a cartesian plane from -1 to 1.
                 let u = sample_i / width as f32 - 0.5;
                 let v = 1.0 - sample_j / height as f32 - 0.5;
 
-                let ray = camera.get_ray(u, v);
+                // Rays blocked inside a lens system bring back no light.
+                let sample = match (camera.get_ray(u, v), spectral) {
+                    (Some(ray), true) => {
+                        let wavelengths = spectrum::sample_wavelengths();
+                        let ray = ray.with_wavelengths(Some(wavelengths));
+                        spectrum::to_rgb(ray.trace(&world, max_depth), wavelengths)
+                    }
+                    (Some(ray), false) => ray.trace(&world, max_depth),
+                    (None, _) => Vec3::zero(),
+                };
+                let sample = sample * camera.exposure();
+
+                for dj in -reach..=reach {
+                    for di in -reach..=reach {
+                        let (i, j) = (x as i32 + di, y as i32 + dj);
+                        let weight = filter.weight(i as f32 - sample_i, j as f32 - sample_j);
 
-                let sample = ray.trace(&world, max_depth);
-                samples.push(sample);
+                        let splat = &mut splats[((dj + reach) * side + di + reach) as usize];
+                        splat.0 += sample * weight;
+                        splat.1 += weight;
+                    }
+                }
             }
 
-            let c = Color::from_samples(samples);
+            let mut neighbours = Vec::new();
+            for dj in -reach..=reach {
+                for di in -reach..=reach {
+                    let (i, j) = (x as i32 + di, y as i32 + dj);
+                    let (value, weight) = splats[((dj + reach) * side + di + reach) as usize];
+
+                    if i >= 0 && j >= 0 && i < width as i32 && j < height as i32 && weight != 0.0 {
+                        neighbours.push((i as u32, j as u32, value, weight));
+                    }
+                }
+            }
 
-            tx.send((x, y, c)).expect("wtf");
+            tx.send(neighbours).expect("wtf");
         });
     });
 
     let mut now = Instant::now();
-    while let Ok((x, y, c)) = rx.recv() {
-        pm.update(x, y, c);
+    while let Ok(neighbours) = rx.recv() {
+        for (x, y, value, weight) in neighbours {
+            pm.splat(x, y, value, weight);
+        }
 
         if now.elapsed().as_millis() >= 16 {
             now = Instant::now();
@@ -146,24 +336,49 @@ fn main() {
 
 pub trait Hittable {
     fn hit(&self, ray: &Ray, min: f32, max: f32) -> Option<Hit>;
+
+    // Like `hit`, but only against solid surfaces, passing through participating media
+    // whose hits fall at random distances.
+    fn hit_surface(&self, ray: &Ray, min: f32, max: f32) -> Option<Hit> {
+        self.hit(ray, min, max)
+    }
+
+    // The middle of the object, for cameras to focus on.
+    fn centroid(&self) -> Option<Vec3> {
+        None
+    }
 }
 
+#[derive(Clone)]
 pub struct Hit {
     pub t: f32,
     pub point: Vec3,
     pub normal: Vec3,
     pub front_face: bool,
     pub material: Material,
+    pub incident_ior: f32,
+    pub transmitted_ior: f32,
+    pub uv: Vec2,
+    // Surface derivatives with respect to the uv coordinates.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
 }
 
 impl Hit {
     pub fn new(t: f32, point: Vec3, normal: Vec3, front_face: bool, material: Material) -> Self {
+        let (dpdu, dpdv) = orthonormal_basis(normal);
+
         Self {
             t,
             point,
             material,
             normal,
             front_face,
+            incident_ior: 1.0,
+            transmitted_ior: 1.0,
+            uv: Vec2::zero(),
+            dpdu,
+            dpdv,
         }
     }
 
@@ -176,4 +391,22 @@ impl Hit {
             self.normal = -outward_normal;
         }
     }
+
+    pub fn set_surface(&mut self, uv: Vec2, dpdu: Vec3, dpdv: Vec3) {
+        self.uv = uv;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+    }
+
+    // Unit tangent and bitangent completing a right handed frame with the shading normal.
+    pub fn tangent_frame(&self) -> (Vec3, Vec3) {
+        let tangent = self.dpdu - self.normal * self.normal.dot(self.dpdu);
+
+        if tangent.mag_sq() < 1e-12 {
+            return orthonormal_basis(self.normal);
+        }
+
+        let tangent = tangent.normalized();
+        (tangent, self.normal.cross(tangent))
+    }
 }
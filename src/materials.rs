@@ -1,15 +1,69 @@
-use rand::{prelude::*, random};
-use ultraviolet::Vec3;
+use std::sync::Arc;
+
+use rand::{prelude::*, random, seq::SliceRandom};
+use ultraviolet::{Mat4, Vec2, Vec3, Vec4};
 
 use crate::color::Color;
+use crate::layered::Coat;
+use crate::medium::Medium;
+use crate::merl::MerlBrdf;
+use crate::microfacet::{
+    fresnel_dielectric, fresnel_schlick, sample_visible_normal, smith_g1, Frame,
+};
+use crate::pixmap::PixMap;
+use crate::principled::Principled;
 use crate::shapes::Sphere;
+use crate::spectrum::Ior;
 use crate::{Hit, Ray};
 
 #[derive(Clone)]
 pub enum Texture {
     Solid(Color),
-    Checker(Box<Texture>, Box<Texture>),
-    Perlin(PerlinNoise),
+    // An exact value, for factors and masks finer than the 8 bits of a color.
+    Constant(Vec3),
+    // Checker and noise textures take the frequency they are sampled at.
+    Checker(Box<Texture>, Box<Texture>, f32),
+    Perlin(PerlinNoise, f32),
+    Turbulence(PerlinNoise, f32),
+    Fbm(PerlinNoise, f32, Octaves),
+    Marble(PerlinNoise, f32),
+    Wood(PerlinNoise, f32),
+    // Nodes that combine or remap other textures.
+    Transform(Box<Texture>, Mat4),
+    Mix(Box<Texture>, Box<Texture>, Box<Texture>),
+    Multiply(Box<Texture>, Box<Texture>),
+    Add(Box<Texture>, Box<Texture>),
+    Ramp(Box<Texture>, Vec<(f32, Vec3)>),
+    Triplanar(Box<Texture>, f32),
+    // Images are looked up by the x and y of the lookup point, so are usually placed
+    // inside a `Uv` node which swaps the point for the surface uv coordinates.
+    Image(Arc<PixMap>),
+    Uv(Box<Texture>),
+}
+
+// Layering of fractal noise, where every octave is sampled at `lacunarity` times the
+// frequency and `gain` times the amplitude of the previous one.
+#[derive(Clone, Copy, Debug)]
+pub struct Octaves {
+    pub count: u32,
+    pub lacunarity: f32,
+    pub gain: f32,
+}
+
+impl Octaves {
+    pub fn new(count: u32, lacunarity: f32, gain: f32) -> Self {
+        Self {
+            count,
+            lacunarity,
+            gain,
+        }
+    }
+}
+
+impl Default for Octaves {
+    fn default() -> Self {
+        Self::new(7, 2.0, 0.5)
+    }
 }
 
 #[derive(Clone)]
@@ -18,86 +72,263 @@ pub struct PerlinNoise {
     x_permute: Vec<usize>,
     y_permute: Vec<usize>,
     z_permute: Vec<usize>,
-    random_values: Vec<f32>,
+    gradients: Vec<Vec3>,
 }
 
 impl PerlinNoise {
+    // The lattice repeats every `size` units, which must be a power of two.
     pub fn new(size: usize) -> Self {
         Self {
-            size: size,
+            size,
             x_permute: Self::generate_permutation(size),
             y_permute: Self::generate_permutation(size),
             z_permute: Self::generate_permutation(size),
-            random_values: Self::generate_randoms(size),
+            gradients: Self::generate_gradients(size),
         }
     }
 
+    // Gradient noise in the range -1 to 1.
     pub fn noise(&self, point: &Vec3) -> f32 {
-        let mut u = point.x - point.x.floor();
-        let mut v = point.y - point.y.floor();
-        let mut w = point.z - point.z.floor();
+        let (x, y, z) = (point.x.floor(), point.y.floor(), point.z.floor());
 
-        u = u * u * (3.0 - 2.0 * u);
-        v = v * v * (3.0 - 2.0 * v);
-        w = w * w * (3.0 - 2.0 * w);
+        let u = point.x - x;
+        let v = point.y - y;
+        let w = point.z - z;
 
-        let i = 4 * point.x as usize & (self.size - 1);
-        let j = 4 * point.y as usize & (self.size - 1);
-        let k = 4 * point.z as usize & (self.size - 1);
+        // Hermite smoothing of the interpolation weights.
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
 
-        self.random_values[self.x_permute[i] ^ self.y_permute[j] ^ self.z_permute[k]]
-    }
+        let mask = self.size - 1;
+        let (i, j, k) = (x as i64, y as i64, z as i64);
 
-    fn generate_permutation(count: usize) -> Vec<usize> {
-        let mut p = Vec::new();
+        let mut accum = 0.0;
 
-        for i in 0..count {
-            p.push(i);
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = self.gradients[self.x_permute[(i + di) as usize & mask]
+                        ^ self.y_permute[(j + dj) as usize & mask]
+                        ^ self.z_permute[(k + dk) as usize & mask]];
+
+                    let (fi, fj, fk) = (di as f32, dj as f32, dk as f32);
+                    let offset = Vec3::new(u - fi, v - fj, w - fk);
+
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * gradient.dot(offset);
+                }
+            }
         }
 
-        for i in (count - 1)..=0 {
-            let target = thread_rng().gen_range(0, i);
-            let tmp = p[i];
-            p[i] = p[target];
-            p[target] = tmp;
+        accum
+    }
+
+    // Sum of the absolute value of noise over doubling frequencies.
+    pub fn turbulence(&self, point: &Vec3, octaves: u32) -> f32 {
+        let mut accum = 0.0;
+        let mut point = *point;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(&point).abs();
+            weight *= 0.5;
+            point *= 2.0;
         }
 
-        p
+        accum
     }
 
-    fn generate_randoms(count: usize) -> Vec<f32> {
-        let mut randoms = Vec::new();
+    // Fractal Brownian motion, normalised back into the range -1 to 1.
+    pub fn fbm(&self, point: &Vec3, octaves: Octaves) -> f32 {
+        let mut accum = 0.0;
+        let mut total = 0.0;
+        let mut point = *point;
+        let mut amplitude = 1.0;
+
+        for _ in 0..octaves.count {
+            accum += amplitude * self.noise(&point);
+            total += amplitude;
+            amplitude *= octaves.gain;
+            point *= octaves.lacunarity;
+        }
 
-        for _ in 0..count {
-            randoms.push(random());
+        match total > 0.0 {
+            true => accum / total,
+            false => 0.0,
         }
+    }
+
+    fn generate_permutation(count: usize) -> Vec<usize> {
+        let mut p = (0..count).collect::<Vec<_>>();
+        p.shuffle(&mut thread_rng());
+        p
+    }
 
-        randoms
+    fn generate_gradients(count: usize) -> Vec<Vec3> {
+        (0..count).map(|_| random_point_lambertian()).collect()
     }
 }
 
 impl Texture {
     pub fn value(&self, hit: &Hit) -> Vec3 {
+        self.value_at(hit.point, hit)
+    }
+
+    // Evaluates the texture at an arbitrary lookup point, which nodes such as
+    // `Transform` and `Triplanar` move away from the hit point.
+    pub fn value_at(&self, point: Vec3, hit: &Hit) -> Vec3 {
+        self.value_with(point, hit.uv, hit)
+    }
+
+    // Evaluates the texture at a lookup point and uv coordinates of its own, such as the
+    // nearby points a bump map compares heights at.
+    pub fn value_with(&self, point: Vec3, uv: Vec2, hit: &Hit) -> Vec3 {
         match self {
             Self::Solid(c) => c.into(),
-            Self::Checker(a, b) => {
-                let sines = (10.0 * hit.point.x).sin()
-                    * (10.0 * hit.point.y).sin()
-                    * (10.0 * hit.point.z).sin();
-                if sines < 0.0 {
-                    a.value(hit)
+            Self::Constant(value) => *value,
+            Self::Checker(a, b, frequency) => {
+                // Same cells as the sign of sin(x) * sin(y) * sin(z), but still a
+                // checkerboard when one coordinate is flattened to zero.
+                let p = point * *frequency / std::f32::consts::PI;
+                let cells = p.x.floor() + p.y.floor() + p.z.floor();
+                if cells.rem_euclid(2.0) >= 1.0 {
+                    a.value_with(point, uv, hit)
                 } else {
-                    b.value(hit)
+                    b.value_with(point, uv, hit)
                 }
             }
-            Self::Perlin(noise) => Vec3::new(1.0, 1.0, 1.0) * noise.noise(&hit.point),
+            Self::Perlin(noise, frequency) => {
+                let n = noise.noise(&(point * *frequency));
+                Vec3::broadcast(0.5 * (1.0 + n))
+            }
+            Self::Turbulence(noise, frequency) => {
+                let n = noise.turbulence(&(point * *frequency), 7);
+                Vec3::broadcast(n.min(1.0))
+            }
+            Self::Fbm(noise, frequency, octaves) => {
+                let n = noise.fbm(&(point * *frequency), *octaves);
+                Vec3::broadcast(0.5 * (1.0 + n))
+            }
+            Self::Marble(noise, frequency) => {
+                let p = point * *frequency;
+                let veins = (p.z + 10.0 * noise.turbulence(&p, 7)).sin();
+                Vec3::broadcast(0.5 * (1.0 + veins))
+            }
+            Self::Wood(noise, frequency) => {
+                let p = point * *frequency;
+                let rings = (p.x * p.x + p.z * p.z).sqrt() + 2.0 * noise.noise(&p);
+                Vec3::broadcast(rings - rings.floor())
+            }
+            Self::Transform(texture, transform) => {
+                let p = *transform * point.into_homogeneous_point();
+                texture.value_with(p.xyz(), uv, hit)
+            }
+            Self::Mix(a, b, factor) => {
+                let t = factor.scalar_with(point, uv, hit).max(0.0).min(1.0);
+                (1.0 - t) * a.value_with(point, uv, hit) + t * b.value_with(point, uv, hit)
+            }
+            Self::Multiply(a, b) => a.value_with(point, uv, hit) * b.value_with(point, uv, hit),
+            Self::Add(a, b) => a.value_with(point, uv, hit) + b.value_with(point, uv, hit),
+            Self::Ramp(texture, stops) => ramp(stops, texture.scalar_with(point, uv, hit)),
+            Self::Triplanar(texture, sharpness) => {
+                let weights = hit.normal.abs().map(|n| n.powf(*sharpness));
+                let weights = weights / (weights.x + weights.y + weights.z);
+
+                weights.x * texture.value_with(Vec3::new(point.y, point.z, 0.0), uv, hit)
+                    + weights.y * texture.value_with(Vec3::new(point.z, point.x, 0.0), uv, hit)
+                    + weights.z * texture.value_with(Vec3::new(point.x, point.y, 0.0), uv, hit)
+            }
+            Self::Image(image) => image.sample(point.x, point.y),
+            Self::Uv(texture) => texture.value_with(Vec3::new(uv.x, uv.y, 0.0), uv, hit),
         }
     }
 
+    // The texture as a single channel, for use as a factor, mask or height.
+    pub fn scalar(&self, hit: &Hit) -> f32 {
+        self.scalar_at(hit.point, hit)
+    }
+
+    pub fn scalar_at(&self, point: Vec3, hit: &Hit) -> f32 {
+        self.scalar_with(point, hit.uv, hit)
+    }
+
+    pub fn scalar_with(&self, point: Vec3, uv: Vec2, hit: &Hit) -> f32 {
+        let value = self.value_with(point, uv, hit);
+        (value.x + value.y + value.z) / 3.0
+    }
+
     pub fn solid(r: u8, g: u8, b: u8) -> Self {
         let color = Color::new(r, g, b);
         Self::Solid(color)
     }
+
+    pub fn constant(value: f32) -> Self {
+        Self::Constant(Vec3::broadcast(value))
+    }
+
+    pub fn checker(a: Texture, b: Texture, frequency: f32) -> Self {
+        Self::Checker(Box::new(a), Box::new(b), frequency)
+    }
+
+    pub fn mix(a: Texture, b: Texture, factor: Texture) -> Self {
+        Self::Mix(Box::new(a), Box::new(b), Box::new(factor))
+    }
+
+    pub fn multiply(a: Texture, b: Texture) -> Self {
+        Self::Multiply(Box::new(a), Box::new(b))
+    }
+
+    pub fn add(a: Texture, b: Texture) -> Self {
+        Self::Add(Box::new(a), Box::new(b))
+    }
+
+    // Maps the scalar value of the texture onto colors interpolated between the stops,
+    // given as increasing positions from 0 to 1.
+    pub fn ramp(self, stops: Vec<(f32, Vec3)>) -> Self {
+        Self::Ramp(Box::new(self), stops)
+    }
+
+    // Projects the texture along the three axes, blending between them by the surface
+    // normal. Higher sharpness gives tighter seams.
+    pub fn triplanar(self, sharpness: f32) -> Self {
+        Self::Triplanar(Box::new(self), sharpness)
+    }
+
+    pub fn uv(self) -> Self {
+        Self::Uv(Box::new(self))
+    }
+
+    // Stretches the texture by the given factor along each axis.
+    pub fn scaled(self, scale: Vec3) -> Self {
+        let inverse = Vec4::new(1.0 / scale.x, 1.0 / scale.y, 1.0 / scale.z, 1.0);
+        Self::Transform(Box::new(self), Mat4::from_nonuniform_scale(inverse))
+    }
+
+    pub fn rotated(self, roll: f32, pitch: f32, yaw: f32) -> Self {
+        let rotation = Mat4::from_euler_angles(roll, pitch, yaw).inversed();
+        Self::Transform(Box::new(self), rotation)
+    }
+
+    pub fn translated(self, offset: Vec3) -> Self {
+        Self::Transform(Box::new(self), Mat4::from_translation(-offset))
+    }
+}
+
+fn ramp(stops: &[(f32, Vec3)], t: f32) -> Vec3 {
+    let upper = stops.iter().position(|(position, _)| *position > t);
+
+    match upper {
+        Some(0) => stops[0].1,
+        Some(i) => {
+            let (a, b) = (stops[i - 1], stops[i]);
+            let f = (t - a.0) / (b.0 - a.0);
+            (1.0 - f) * a.1 + f * b.1
+        }
+        None => stops.last().map(|stop| stop.1).unwrap_or_else(Vec3::zero),
+    }
 }
 
 impl Default for Texture {
@@ -108,13 +339,131 @@ impl Default for Texture {
 
 pub trait Scatter {
     fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Reflection>;
+
+    fn emitted(&self, _hit: &Hit) -> Vec3 {
+        Vec3::zero()
+    }
 }
 
 #[derive(Clone)]
 pub enum Material {
-    Dielectric(Vec3, f32),
+    // Absorption coefficient, index of refraction, priority among overlapping media and an
+    // optional thin film coating.
+    Dielectric(Vec3, Ior, u32, Option<ThinFilm>),
     Metal(Texture, f32),
     Lambertian(Texture),
+    Isotropic(Texture),
+    HenyeyGreenstein(Texture, f32),
+    Emissive(Texture, f32),
+    // Perturb the shading normal of another material, by the slope of a height texture
+    // scaled by a strength or by a tangent space normal map.
+    Bump(Box<Material>, Texture, f32),
+    NormalMap(Box<Material>, Texture),
+    Principled(Box<Principled>),
+    Layered(Box<Material>, Coat),
+    // Metal with separate GGX roughness along the tangent and bitangent, optionally turning
+    // the tangent by a texture holding directions in tangent space like a flow map.
+    Anisotropic(Texture, f32, f32, Option<Texture>),
+    Subsurface(Subsurface),
+    // Randomly picks the second material with the probability given by the scalar texture.
+    Mix(Box<Material>, Box<Material>, Texture),
+    // Lets rays straight through where the scalar alpha texture is below a random threshold.
+    Cutout(Box<Material>, Texture),
+    // An infinitely thin slab of glass, such as a window pane modelled by a single quad. The
+    // tint is the color transmitted straight through the slab.
+    ThinDielectric(Texture, f32),
+    // Reflectance measured from a real material, such as one from the MERL database.
+    Measured(Arc<MerlBrdf>),
+}
+
+// A coating a few hundred nanometres thick whose interference colors the reflections, like
+// a soap bubble or an oil slick. The variation texture scales the thickness across the
+// surface.
+#[derive(Clone)]
+pub struct ThinFilm {
+    pub ior: f32,
+    pub thickness: f32,
+    pub variation: Texture,
+}
+
+impl ThinFilm {
+    pub fn new(ior: f32, thickness: f32, variation: Texture) -> Self {
+        Self {
+            ior,
+            thickness,
+            variation,
+        }
+    }
+
+    // Reflectance at each of three wavelengths, from the Airy sum of the waves bouncing
+    // between the two sides of the film.
+    pub fn reflectance(&self, cos_theta: f32, hit: &Hit, wavelengths: Vec3) -> Vec3 {
+        let (n1, n2, n3) = (hit.incident_ior, self.ior, hit.transmitted_ior);
+        let thickness = self.thickness * self.variation.scalar(hit);
+
+        let sin_sq = 1.0 - cos_theta * cos_theta;
+        let cosine_in = |n: f32| {
+            let sin_sq = sin_sq * (n1 / n).powi(2);
+            match sin_sq < 1.0 {
+                true => Some((1.0 - sin_sq).sqrt()),
+                false => None,
+            }
+        };
+
+        let (cos2, cos3) = match (cosine_in(n2), cosine_in(n3)) {
+            (Some(cos2), Some(cos3)) => (cos2, cos3),
+            _ => return Vec3::one(),
+        };
+
+        let s12 = (n1 * cos_theta - n2 * cos2) / (n1 * cos_theta + n2 * cos2);
+        let p12 = (n2 * cos_theta - n1 * cos2) / (n2 * cos_theta + n1 * cos2);
+        let s23 = (n2 * cos2 - n3 * cos3) / (n2 * cos2 + n3 * cos3);
+        let p23 = (n3 * cos2 - n2 * cos3) / (n3 * cos2 + n2 * cos3);
+
+        let airy = |r12: f32, r23: f32, phase: f32| {
+            let cross = 2.0 * r12 * r23 * phase.cos();
+            (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+        };
+
+        let wavelength = |lambda: f32| {
+            let phase = 4.0 * std::f32::consts::PI * n2 * thickness * cos2 / lambda;
+            0.5 * (airy(s12, s23, phase) + airy(p12, p23, phase))
+        };
+
+        wavelengths.map(wavelength)
+    }
+}
+
+// A translucent material whose light wanders around inside the object before leaving it,
+// like skin, wax or milk. Paths inside take a random walk with the given single scattering
+// albedo and mean free path per channel until they find their way back out of the
+// boundary.
+#[derive(Clone)]
+pub struct Subsurface {
+    pub albedo: Vec3,
+    pub mean_free_path: Vec3,
+    pub anisotropy: f32,
+    pub ior: f32,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Vec3, mean_free_path: Vec3, anisotropy: f32, ior: f32) -> Self {
+        Self {
+            albedo,
+            mean_free_path,
+            anisotropy,
+            ior,
+        }
+    }
+
+    pub fn medium(&self) -> Medium {
+        let extinction = self.mean_free_path.map(|d| 1.0 / d.max(1e-6));
+        let scattering = self.albedo * extinction;
+        let absorption = extinction - scattering;
+
+        Medium::new(Ior::Constant(self.ior), absorption, u32::MAX)
+            .with_scattering(scattering, self.anisotropy)
+    }
 }
 
 impl Default for Material {
@@ -123,34 +472,91 @@ impl Default for Material {
     }
 }
 
+impl Material {
+    // The medium filling the inside of objects made of this material, if any.
+    pub fn medium(&self) -> Option<Medium> {
+        match self {
+            Material::Dielectric(absorption, ior, priority, _) => {
+                Some(Medium::new(*ior, *absorption, *priority))
+            }
+            Material::Bump(base, ..) | Material::NormalMap(base, _) | Material::Cutout(base, _) => {
+                base.medium()
+            }
+            Material::Layered(base, _) => base.medium(),
+            Material::Subsurface(subsurface) => Some(subsurface.medium()),
+            // The medium belongs to the object rather than to each scatter, so a mix takes the
+            // first of its materials that has one, whichever of them scatters the ray.
+            Material::Mix(a, b, _) => a.medium().or_else(|| b.medium()),
+            Material::Principled(principled) => principled.medium(),
+            _ => None,
+        }
+    }
+}
+
 impl Scatter for Material {
     fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Reflection> {
         match self {
-            Material::Dielectric(albedo, ior) => {
-                let eta_ratio = match hit.front_face {
-                    true => 1.0 / ior,
-                    false => *ior,
-                };
-
+            Material::Dielectric(.., film) => scatter_dielectric(ray, hit, film.as_ref()),
+            Material::Subsurface(_) => scatter_dielectric(ray, hit, None),
+            Material::Mix(a, b, factor) => match random::<f32>() < factor.scalar(hit) {
+                true => b.scatter(ray, hit),
+                false => a.scatter(ray, hit),
+            },
+            Material::ThinDielectric(tint, ior) => {
                 let unit_direction = ray.direction().normalized();
-
                 let cos_theta = -unit_direction.dot(hit.normal).min(1.0);
-                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-
-                let ref_vec = match eta_ratio * sin_theta > 1.0
-                    || random::<f32>() < schlick(cos_theta, eta_ratio)
-                {
-                    true => reflect(unit_direction, hit.normal),
-                    false => refract(unit_direction, hit.normal, eta_ratio),
-                };
 
-                let ref_out = Reflection {
-                    attenuation: *albedo,
-                    scatter: Ray::new(hit.point, ref_vec, ray.time()),
+                let eta_ratio = hit.incident_ior / ior;
+                let r = fresnel_dielectric(cos_theta, eta_ratio);
+                let t = 1.0 - r;
+
+                // Light bounces back and forth inside the slab, losing some of itself each
+                // time it crosses, which sums up as a geometric series.
+                let sin_sq = (1.0 - cos_theta * cos_theta) * eta_ratio * eta_ratio;
+                let slant = 1.0 / (1.0 - sin_sq).max(0.0001).sqrt();
+                let pass = tint.value(hit).map(|c| c.max(0.0).powf(slant));
+
+                let denominator = Vec3::one() - r * r * pass * pass;
+                let reflectance = Vec3::broadcast(r) + t * t * r * pass * pass / denominator;
+                let transmittance = t * t * pass / denominator;
+
+                let (reflected, transmitted) = (
+                    (reflectance.x + reflectance.y + reflectance.z) / 3.0,
+                    (transmittance.x + transmittance.y + transmittance.z) / 3.0,
+                );
+                let chance = reflected / (reflected + transmitted).max(1e-6);
+
+                let reflection = match random::<f32>() < chance {
+                    true => Reflection {
+                        attenuation: reflectance / chance,
+                        scatter: Ray::new(
+                            hit.point,
+                            reflect(unit_direction, hit.normal),
+                            ray.time(),
+                        ),
+                        spectral: false,
+                        pass_through: false,
+                    },
+                    false => Reflection {
+                        attenuation: transmittance / (1.0 - chance),
+                        scatter: Ray::new(hit.point, ray.direction(), ray.time()),
+                        spectral: false,
+                        pass_through: false,
+                    },
                 };
 
-                Some(ref_out)
+                Some(reflection)
             }
+            Material::Measured(brdf) => brdf.scatter(ray, hit),
+            Material::Cutout(base, alpha) => match random::<f32>() < alpha.scalar(hit) {
+                true => base.scatter(ray, hit),
+                false => Some(Reflection {
+                    attenuation: Vec3::one(),
+                    scatter: Ray::new(hit.point, ray.direction(), ray.time()),
+                    spectral: false,
+                    pass_through: true,
+                }),
+            },
             Material::Metal(albedo, blur) => {
                 let reflected = reflect(ray.direction().normalized(), hit.normal);
                 let fuzz = blur.max(0.0).min(1.0) * Sphere::unit().random_point_within();
@@ -160,6 +566,8 @@ impl Scatter for Material {
                     let reflection = Reflection {
                         attenuation: albedo.value(hit),
                         scatter,
+                        spectral: false,
+                        pass_through: false,
                     };
 
                     Some(reflection)
@@ -173,14 +581,182 @@ impl Scatter for Material {
                 let reflection = Reflection {
                     attenuation: albedo.value(hit),
                     scatter: Ray::new(hit.point, scatter_direction, ray.time()),
+                    spectral: false,
+                    pass_through: false,
                 };
 
                 Some(reflection)
             }
+            Material::Isotropic(albedo) => {
+                let reflection = Reflection {
+                    attenuation: albedo.value(hit),
+                    scatter: Ray::new(hit.point, random_point_lambertian(), ray.time()),
+                    spectral: false,
+                    pass_through: false,
+                };
+
+                Some(reflection)
+            }
+            Material::HenyeyGreenstein(albedo, g) => {
+                let direction = henyey_greenstein(ray.direction().normalized(), *g);
+
+                let reflection = Reflection {
+                    attenuation: albedo.value(hit),
+                    scatter: Ray::new(hit.point, direction, ray.time()),
+                    spectral: false,
+                    pass_through: false,
+                };
+
+                Some(reflection)
+            }
+            Material::Emissive(..) => None,
+            Material::Bump(base, height, strength) => match bump(hit, height, *strength) {
+                Some(bumped) => base.scatter(ray, &bumped),
+                None => base.scatter(ray, hit),
+            },
+            Material::NormalMap(base, map) => base.scatter(ray, &normal_map(hit, map)),
+            Material::Principled(principled) => principled.scatter(ray, hit),
+            Material::Layered(base, coat) => coat.scatter(base, ray, hit),
+            Material::Anisotropic(albedo, alpha_x, alpha_y, direction) => {
+                let (mut tangent, _) = hit.tangent_frame();
+
+                if let Some(direction) = direction {
+                    let (t, b) = hit.tangent_frame();
+                    let d = direction.value(hit) * 2.0 - Vec3::one();
+                    let turned = d.x * t + d.y * b;
+
+                    if turned.mag_sq() > 1e-8 {
+                        tangent = turned.normalized();
+                    }
+                }
+
+                let frame = Frame::new(tangent, hit.normal.cross(tangent), hit.normal);
+                let wo = frame.to_local(-ray.direction().normalized());
+                let (ax, ay) = (alpha_x.max(0.001), alpha_y.max(0.001));
+
+                if wo.z <= 0.0 {
+                    return None;
+                }
+
+                let m = sample_visible_normal(wo, ax, ay);
+                let wi = reflect(-wo, m);
+
+                if wi.z <= 0.0 {
+                    return None;
+                }
+
+                let reflection = Reflection {
+                    attenuation: fresnel_schlick(albedo.value(hit), wo.dot(m))
+                        * smith_g1(wi, ax, ay),
+                    scatter: Ray::new(hit.point, frame.to_world(wi), ray.time()),
+                    spectral: false,
+                    pass_through: false,
+                };
+
+                Some(reflection)
+            }
+        }
+    }
+
+    fn emitted(&self, hit: &Hit) -> Vec3 {
+        match self {
+            Material::Emissive(color, strength) => color.value(hit) * *strength,
+            Material::Bump(base, ..) | Material::NormalMap(base, _) => base.emitted(hit),
+            Material::Layered(base, _) => base.emitted(hit),
+            Material::Mix(a, b, factor) => {
+                let t = factor.scalar(hit).max(0.0).min(1.0);
+                (1.0 - t) * a.emitted(hit) + t * b.emitted(hit)
+            }
+            Material::Cutout(base, alpha) => {
+                alpha.scalar(hit).max(0.0).min(1.0) * base.emitted(hit)
+            }
+            _ => Vec3::zero(),
         }
     }
 }
 
+// Smooth dielectric interface, optionally coated by a thin film.
+fn scatter_dielectric(ray: &Ray, hit: &Hit, film: Option<&ThinFilm>) -> Option<Reflection> {
+    let eta_ratio = hit.incident_ior / hit.transmitted_ior;
+
+    let unit_direction = ray.direction().normalized();
+
+    let cos_theta = -unit_direction.dot(hit.normal).min(1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+    // Films are evaluated at the wavelengths the ray carries in spectral mode, and otherwise
+    // at a red, green and blue one.
+    let rgb = Vec3::new(650.0, 532.0, 450.0);
+    let reflectance = match film {
+        Some(film) => film.reflectance(cos_theta, hit, ray.wavelengths().unwrap_or(rgb)),
+        None => Vec3::broadcast(schlick(cos_theta, eta_ratio)),
+    };
+
+    // Pick by the average reflectance, weighting each channel to match.
+    let chance = (reflectance.x + reflectance.y + reflectance.z) / 3.0;
+
+    let (ref_vec, attenuation) = match eta_ratio * sin_theta > 1.0 {
+        true => (reflect(unit_direction, hit.normal), Vec3::one()),
+        false => match random::<f32>() < chance {
+            true => (reflect(unit_direction, hit.normal), reflectance / chance),
+            false => (
+                refract(unit_direction, hit.normal, eta_ratio),
+                (Vec3::one() - reflectance) / (1.0 - chance),
+            ),
+        },
+    };
+
+    let ref_out = Reflection {
+        attenuation,
+        scatter: Ray::new(hit.point, ref_vec, ray.time()),
+        spectral: film.is_some() && ray.wavelengths().is_some(),
+        pass_through: false,
+    };
+
+    Some(ref_out)
+}
+
+// Finite differences of the height along the surface derivatives, so that heights mapped by
+// both uv and position shift with the surface.
+fn bump(hit: &Hit, height: &Texture, strength: f32) -> Option<Hit> {
+    let delta = 0.0005;
+    let base = height.scalar(hit);
+
+    let slope = |point: Vec3, uv: Vec2| (height.scalar_with(point, uv, hit) - base) / delta;
+    let slope_u = slope(hit.point + delta * hit.dpdu, hit.uv + Vec2::new(delta, 0.0));
+    let slope_v = slope(hit.point + delta * hit.dpdv, hit.uv + Vec2::new(0.0, delta));
+
+    let dpdu = hit.dpdu + strength * slope_u * hit.normal;
+    let dpdv = hit.dpdv + strength * slope_v * hit.normal;
+    let normal = dpdu.cross(dpdv);
+
+    // A degenerate frame leaves the surface as it is.
+    if normal.mag_sq() < 1e-12 {
+        return None;
+    }
+
+    let mut bumped = hit.clone();
+    bumped.normal = match normal.dot(hit.normal) < 0.0 {
+        true => -normal.normalized(),
+        false => normal.normalized(),
+    };
+    bumped.dpdu = dpdu;
+    bumped.dpdv = dpdv;
+
+    Some(bumped)
+}
+
+// Normal maps store tangent space normals with each component remapped into 0 to 1.
+fn normal_map(hit: &Hit, map: &Texture) -> Hit {
+    let (tangent, bitangent) = hit.tangent_frame();
+    let n = map.value(hit) * 2.0 - Vec3::one();
+
+    let mut mapped = hit.clone();
+    mapped.normal = (n.x * tangent + n.y * bitangent + n.z * hit.normal).normalized();
+
+    mapped
+}
+
 // Diffuse
 fn random_point_lambertian() -> Vec3 {
     let a = random::<f32>() * 2.0 * std::f32::consts::PI;
@@ -190,11 +766,42 @@ fn random_point_lambertian() -> Vec3 {
     Vec3::new(r * a.cos(), r * a.sin(), z)
 }
 
-fn reflect(v: Vec3, normal: Vec3) -> Vec3 {
+// Phase function sampling, where positive `g` favours forward scattering.
+pub fn henyey_greenstein(direction: Vec3, g: f32) -> Vec3 {
+    let cos_theta = match g.abs() < 0.001 {
+        true => 1.0 - 2.0 * random::<f32>(),
+        false => {
+            let sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * random::<f32>());
+            (1.0 + g * g - sq * sq) / (2.0 * g)
+        }
+    };
+
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = random::<f32>() * 2.0 * std::f32::consts::PI;
+
+    let (tangent, bitangent) = orthonormal_basis(direction);
+
+    sin_theta * (phi.cos() * tangent + phi.sin() * bitangent) + cos_theta * direction
+}
+
+// Two unit vectors perpendicular to `n` and to each other.
+pub fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    let helper = match n.x.abs() > 0.9 {
+        true => Vec3::unit_y(),
+        false => Vec3::unit_x(),
+    };
+
+    let tangent = helper.cross(n).normalized();
+    let bitangent = n.cross(tangent);
+
+    (tangent, bitangent)
+}
+
+pub fn reflect(v: Vec3, normal: Vec3) -> Vec3 {
     v - 2.0 * v.dot(normal) * normal
 }
 
-fn refract(uv: Vec3, normal: Vec3, eta_ratio: f32) -> Vec3 {
+pub fn refract(uv: Vec3, normal: Vec3, eta_ratio: f32) -> Vec3 {
     let cos_theta = -uv.dot(normal);
 
     let parallel = eta_ratio * (uv + cos_theta * normal);
@@ -211,4 +818,8 @@ fn schlick(cosine: f32, ior: f32) -> f32 {
 pub struct Reflection {
     pub attenuation: Vec3,
     pub scatter: Ray,
+    // Whether the attenuation is already given at the ray's wavelengths rather than in RGB.
+    pub spectral: bool,
+    // Whether the ray goes on through a gap in the surface without crossing into it.
+    pub pass_through: bool,
 }
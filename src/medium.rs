@@ -0,0 +1,144 @@
+use rand::random;
+use ultraviolet::Vec3;
+
+use crate::spectrum::Ior;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Medium {
+    pub ior: Ior,
+    pub absorption: Vec3,
+    pub priority: u32,
+    pub scattering: Vec3,
+    pub anisotropy: f32,
+}
+
+impl Medium {
+    pub fn new(ior: Ior, absorption: Vec3, priority: u32) -> Self {
+        Self {
+            ior,
+            absorption,
+            priority,
+            scattering: Vec3::zero(),
+            anisotropy: 0.0,
+        }
+    }
+
+    // Makes light scatter inside the medium, with a Henyey-Greenstein phase function.
+    pub fn with_scattering(mut self, scattering: Vec3, anisotropy: f32) -> Self {
+        self.scattering = scattering;
+        self.anisotropy = anisotropy;
+        self
+    }
+
+    pub fn air() -> Self {
+        Self::new(Ior::Constant(1.0), Vec3::zero(), 0)
+    }
+
+    // Beer-Lambert attenuation of light travelling `distance` through the medium.
+    pub fn absorb(&self, distance: f32) -> Vec3 {
+        self.absorption.map(|a| (-a * distance).exp())
+    }
+
+    // Samples how far light travels towards a surface `distance` away before scattering,
+    // returning the scattering distance if it falls short of the surface along with the
+    // weight of the path up to that point.
+    pub fn free_flight(&self, distance: f32) -> (Option<f32>, Vec3) {
+        if self.scattering.component_max() <= 0.0 {
+            return (None, self.absorb(distance));
+        }
+
+        // Distances are sampled by the average extinction, and each channel reweighted.
+        let extinction = self.absorption + self.scattering;
+        let density = (extinction.x + extinction.y + extinction.z) / 3.0;
+        let flight = -(1.0 - random::<f32>()).ln() / density;
+
+        let transmittance = |d: f32| extinction.map(|e| (-e * d).exp());
+
+        match flight < distance {
+            true => {
+                let pdf = density * (-density * flight).exp();
+                (Some(flight), transmittance(flight) * self.scattering / pdf)
+            }
+            false => {
+                let probability = (-density * distance).exp();
+                (None, transmittance(distance) / probability)
+            }
+        }
+    }
+}
+
+impl PartialEq for Medium {
+    fn eq(&self, other: &Self) -> bool {
+        let same = |a: Vec3, b: Vec3| (a.x, a.y, a.z) == (b.x, b.y, b.z);
+
+        self.ior == other.ior
+            && self.priority == other.priority
+            && self.anisotropy == other.anisotropy
+            && same(self.absorption, other.absorption)
+            && same(self.scattering, other.scattering)
+    }
+}
+
+// The media a path is currently inside of. Overlapping media are resolved by priority, so
+// a liquid with a higher priority than its glass container fills it right up to the wall.
+#[derive(Clone, Debug, Default)]
+pub struct MediumStack {
+    media: Vec<Medium>,
+}
+
+impl MediumStack {
+    pub fn new() -> Self {
+        Self { media: Vec::new() }
+    }
+
+    // The highest priority medium, preferring the most recently entered one on ties.
+    pub fn current(&self) -> Medium {
+        self.media
+            .iter()
+            .max_by_key(|m| m.priority)
+            .copied()
+            .unwrap_or_else(Medium::air)
+    }
+
+    // The medium that would be current after entering or leaving another, without changing
+    // the stack, or none when leaving a medium that was never entered.
+    pub fn current_after(&self, medium: Medium, entering: bool) -> Option<Medium> {
+        if entering {
+            let current = self.current();
+            return match medium.priority >= current.priority {
+                true => Some(medium),
+                false => Some(current),
+            };
+        }
+
+        let leaving = self.media.iter().rposition(|m| *m == medium)?;
+        let after = self
+            .media
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != leaving)
+            .map(|(_, m)| m)
+            .max_by_key(|m| m.priority)
+            .copied()
+            .unwrap_or_else(Medium::air);
+
+        Some(after)
+    }
+
+    pub fn cross(&mut self, medium: Medium, entering: bool) {
+        match entering {
+            true => self.enter(medium),
+            false => self.exit(medium),
+        }
+    }
+
+    pub fn enter(&mut self, medium: Medium) {
+        self.media.push(medium);
+    }
+
+    pub fn exit(&mut self, medium: Medium) {
+        if let Some(i) = self.media.iter().rposition(|m| *m == medium) {
+            self.media.remove(i);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rand::random;
+use ultraviolet::Vec3;
+
+use crate::materials::{reflect, Reflection};
+use crate::microfacet::{cosine_hemisphere, reflection_pdf, sample_visible_normal, Frame};
+use crate::{Hit, Ray};
+
+const THETA_HALF: usize = 90;
+const THETA_DIFF: usize = 90;
+const PHI_DIFF: usize = 180;
+const SAMPLES: usize = THETA_HALF * THETA_DIFF * PHI_DIFF;
+
+const SCALE: [f32; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+// An isotropic BRDF measured by the MERL gonioreflectometer, tabulated over the half and
+// difference angles. Directions are importance sampled by a diffuse and GGX lobe fitted
+// to the data when it is loaded.
+pub struct MerlBrdf {
+    values: Vec<f32>,
+    alpha: f32,
+    specular: f32,
+}
+
+impl MerlBrdf {
+    // Reads a `.binary` file of three i32 dimensions followed by the f64 red, green and blue
+    // tables in turn.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        if bytes.len() < 12 {
+            return Err(invalid("merl header is truncated"));
+        }
+
+        let dimension = |i: usize| {
+            let b = &bytes[i * 4..i * 4 + 4];
+            i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize
+        };
+
+        if (dimension(0), dimension(1), dimension(2)) != (THETA_HALF, THETA_DIFF, PHI_DIFF) {
+            return Err(invalid("merl dimensions are not 90 x 90 x 180"));
+        }
+
+        if bytes.len() != 12 + 3 * SAMPLES * 8 {
+            return Err(invalid("merl size does not match its dimensions"));
+        }
+
+        let values = bytes[12..]
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+            .map(|v| v.max(0.0) as f32)
+            .collect::<Vec<f32>>();
+
+        let mut brdf = Self {
+            values,
+            alpha: 0.5,
+            specular: 0.5,
+        };
+        brdf.fit();
+
+        Ok(brdf)
+    }
+
+    // Reflectance for directions in the local shading frame.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::zero();
+        }
+
+        let half = (wo + wi).normalized();
+        let theta_half = half.z.max(-1.0).min(1.0).acos();
+        let phi_half = half.y.atan2(half.x);
+
+        let diff = rotate(
+            rotate(wi, Vec3::unit_z(), -phi_half),
+            Vec3::unit_y(),
+            -theta_half,
+        );
+        let theta_diff = diff.z.max(-1.0).min(1.0).acos();
+        let phi_diff = diff.y.atan2(diff.x);
+
+        self.lookup(theta_half, theta_diff, phi_diff)
+    }
+
+    pub fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Reflection> {
+        let frame = Frame::from_hit(hit);
+        let wo = frame.to_local(-ray.direction().normalized());
+
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let wi = match random::<f32>() < self.specular {
+            true => reflect(-wo, sample_visible_normal(wo, self.alpha, self.alpha)),
+            false => cosine_hemisphere(),
+        };
+
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        let pdf = self.specular * reflection_pdf(wo, wi, self.alpha, self.alpha)
+            + (1.0 - self.specular) * wi.z / PI;
+
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        let reflection = Reflection {
+            attenuation: self.eval(wo, wi) * wi.z / pdf,
+            scatter: Ray::new(hit.point, frame.to_world(wi), ray.time()),
+            spectral: false,
+            pass_through: false,
+        };
+
+        Some(reflection)
+    }
+
+    fn lookup(&self, theta_half: f32, theta_diff: f32, phi_diff: f32) -> Vec3 {
+        // The half angle is tabulated more densely towards the specular peak.
+        let half_index = match theta_half > 0.0 {
+            true => ((theta_half / FRAC_PI_2).sqrt() * THETA_HALF as f32) as usize,
+            false => 0,
+        };
+        let diff_index = (theta_diff / FRAC_PI_2 * THETA_DIFF as f32) as usize;
+
+        // Reciprocity makes the table symmetric in the difference azimuth.
+        let phi_diff = match phi_diff < 0.0 {
+            true => phi_diff + PI,
+            false => phi_diff,
+        };
+        let phi_index = (phi_diff / PI * PHI_DIFF as f32) as usize;
+
+        let index = phi_index.min(PHI_DIFF - 1)
+            + diff_index.min(THETA_DIFF - 1) * PHI_DIFF
+            + half_index.min(THETA_HALF - 1) * PHI_DIFF * THETA_DIFF;
+
+        Vec3::new(
+            self.values[index] * SCALE[0],
+            self.values[index + SAMPLES] * SCALE[1],
+            self.values[index + 2 * SAMPLES] * SCALE[2],
+        )
+    }
+
+    // Fits the sampling lobes to the retro-reflective slice of the table, taking the floor
+    // as the diffuse part and the width of the peak above it as the GGX roughness.
+    fn fit(&mut self) {
+        let luminance = |c: Vec3| c.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+        let slice = (0..THETA_HALF)
+            .map(|i| {
+                let theta_half = (i as f32 + 0.5) / THETA_HALF as f32;
+                let theta_half = theta_half * theta_half * FRAC_PI_2;
+                (
+                    theta_half,
+                    luminance(self.lookup(theta_half, 0.0, FRAC_PI_2)),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let peak = slice[0].1;
+        let floor = slice.iter().map(|s| s.1).fold(peak, f32::min);
+
+        if peak <= 0.0 || peak - floor <= 0.0 {
+            self.specular = 0.0;
+            return;
+        }
+
+        let half_maximum = floor + 0.5 * (peak - floor);
+        let theta = slice
+            .iter()
+            .find(|s| s.1 <= half_maximum)
+            .map(|s| s.0)
+            .unwrap_or(FRAC_PI_2);
+
+        // Where GGX falls to half of its peak, D(theta) / D(0) = 1 / 2.
+        let cos_sq = theta.cos().powi(2);
+        self.alpha = (theta.sin() / (2.0f32.sqrt() - cos_sq).sqrt())
+            .max(0.01)
+            .min(1.0);
+        self.specular = (1.0 - floor / peak).max(0.1).min(0.9);
+    }
+}
+
+// Rodrigues' rotation of a vector around a unit axis.
+fn rotate(v: Vec3, axis: Vec3, angle: f32) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    v * cos + axis * axis.dot(v) * (1.0 - cos) + axis.cross(v) * sin
+}
@@ -0,0 +1,137 @@
+use std::f32::consts::PI;
+
+use rand::random;
+use ultraviolet::Vec3;
+
+use crate::Hit;
+
+// An orthonormal shading frame, where the local z axis is the shading normal.
+pub struct Frame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3,
+}
+
+impl Frame {
+    pub fn new(tangent: Vec3, bitangent: Vec3, normal: Vec3) -> Self {
+        Self {
+            tangent,
+            bitangent,
+            normal,
+        }
+    }
+
+    pub fn from_hit(hit: &Hit) -> Self {
+        let (tangent, bitangent) = hit.tangent_frame();
+        Self::new(tangent, bitangent, hit.normal)
+    }
+
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            v.dot(self.tangent),
+            v.dot(self.bitangent),
+            v.dot(self.normal),
+        )
+    }
+
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x * self.tangent + v.y * self.bitangent + v.z * self.normal
+    }
+}
+
+// Anisotropic GGX distribution of microfacet normals in the local frame.
+pub fn ggx_d(m: Vec3, ax: f32, ay: f32) -> f32 {
+    if m.z <= 0.0 {
+        return 0.0;
+    }
+
+    let t = (m.x / ax).powi(2) + (m.y / ay).powi(2) + m.z * m.z;
+    1.0 / (PI * ax * ay * t * t)
+}
+
+fn smith_lambda(v: Vec3, ax: f32, ay: f32) -> f32 {
+    let slope = ((ax * v.x).powi(2) + (ay * v.y).powi(2)) / (v.z * v.z);
+    0.5 * (-1.0 + (1.0 + slope).sqrt())
+}
+
+// Smith masking for a single direction.
+pub fn smith_g1(v: Vec3, ax: f32, ay: f32) -> f32 {
+    if v.z <= 0.0 {
+        return 0.0;
+    }
+
+    1.0 / (1.0 + smith_lambda(v, ax, ay))
+}
+
+// Samples a microfacet normal visible from `v`, following Heitz 2018.
+pub fn sample_visible_normal(v: Vec3, ax: f32, ay: f32) -> Vec3 {
+    let vh = Vec3::new(ax * v.x, ay * v.y, v.z).normalized();
+
+    let length_sq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = match length_sq > 0.0 {
+        true => Vec3::new(-vh.y, vh.x, 0.0) / length_sq.sqrt(),
+        false => Vec3::unit_x(),
+    };
+    let t2 = vh.cross(t1);
+
+    let r = random::<f32>().sqrt();
+    let phi = 2.0 * PI * random::<f32>();
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+    Vec3::new(ax * nh.x, ay * nh.y, nh.z.max(1e-6)).normalized()
+}
+
+// Density of reflecting `wo` into `wi` off a visible normal sample.
+pub fn reflection_pdf(wo: Vec3, wi: Vec3, ax: f32, ay: f32) -> f32 {
+    let h = (wo + wi).normalized();
+
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+        return 0.0;
+    }
+
+    smith_g1(wo, ax, ay) * ggx_d(h, ax, ay) / (4.0 * wo.z)
+}
+
+// Cook-Torrance reflection without the Fresnel term, which callers apply themselves.
+pub fn reflection(wo: Vec3, wi: Vec3, ax: f32, ay: f32) -> f32 {
+    let h = (wo + wi).normalized();
+
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+        return 0.0;
+    }
+
+    ggx_d(h, ax, ay) * smith_g1(wo, ax, ay) * smith_g1(wi, ax, ay) / (4.0 * wo.z * wi.z)
+}
+
+// Exact Fresnel reflectance of an unpolarised dielectric interface, where `eta_ratio` is
+// the incident over the transmitted index of refraction.
+pub fn fresnel_dielectric(cos_i: f32, eta_ratio: f32) -> f32 {
+    let cos_i = cos_i.max(0.0).min(1.0);
+    let sin_t_sq = eta_ratio * eta_ratio * (1.0 - cos_i * cos_i);
+
+    if sin_t_sq >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = (1.0 - sin_t_sq).sqrt();
+    let parallel = (cos_i - eta_ratio * cos_t) / (cos_i + eta_ratio * cos_t);
+    let perpendicular = (eta_ratio * cos_i - cos_t) / (eta_ratio * cos_i + cos_t);
+
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+pub fn fresnel_schlick(f0: Vec3, cosine: f32) -> Vec3 {
+    let weight = (1.0 - cosine).max(0.0).min(1.0).powi(5);
+    f0 + (Vec3::one() - f0) * weight
+}
+
+pub fn cosine_hemisphere() -> Vec3 {
+    let r = random::<f32>().sqrt();
+    let phi = 2.0 * PI * random::<f32>();
+
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - r * r).max(0.0).sqrt())
+}
@@ -1,10 +1,15 @@
-use std::fs::File;
-use std::io::Write;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use ultraviolet::Vec3;
 
 use crate::Color;
 
 pub struct PixMap {
     pixels: Vec<Color>,
+    // Running sums of the weighted samples splatted onto each pixel, and of their weights.
+    sums: Vec<(Vec3, f32)>,
     pub width: u32,
     pub height: u32,
 }
@@ -23,7 +28,96 @@ impl PixMap {
             width,
             height,
             pixels: vec![Color::black(); pixel_count as usize],
+            sums: vec![(Vec3::zero(), 0.0); pixel_count as usize],
+        }
+    }
+
+    // Reads a binary (P6) or plain text (P3) PPM image.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut cursor = 0;
+        let mut header = Vec::new();
+
+        while header.len() < 4 {
+            while cursor < bytes.len() && bytes[cursor].is_ascii_whitespace() {
+                cursor += 1;
+            }
+
+            if cursor < bytes.len() && bytes[cursor] == b'#' {
+                while cursor < bytes.len() && bytes[cursor] != b'\n' {
+                    cursor += 1;
+                }
+                continue;
+            }
+
+            let start = cursor;
+            while cursor < bytes.len() && !bytes[cursor].is_ascii_whitespace() {
+                cursor += 1;
+            }
+
+            if start == cursor {
+                return Err(invalid("ppm header is truncated"));
+            }
+
+            header.push(String::from_utf8_lossy(&bytes[start..cursor]).into_owned());
+        }
+
+        let number = |s: &str| {
+            s.parse::<u32>()
+                .map_err(|_| invalid("ppm header is malformed"))
+        };
+        let (width, height, max) = (
+            number(&header[1])?,
+            number(&header[2])?,
+            number(&header[3])?,
+        );
+
+        if width == 0 || height == 0 {
+            return Err(invalid("ppm image must not be empty"));
+        }
+
+        let count = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(3))
+            .ok_or_else(|| invalid("ppm image is too large"))? as usize;
+        let samples = match header[0].as_str() {
+            "P3" => bytes[cursor..]
+                .split(|b| b.is_ascii_whitespace())
+                .filter(|s| !s.is_empty())
+                .map(|s| number(&String::from_utf8_lossy(s)))
+                .collect::<io::Result<Vec<u32>>>()?,
+            "P6" if max < 256 => bytes[(cursor + 1).min(bytes.len())..]
+                .iter()
+                .map(|b| *b as u32)
+                .collect(),
+            "P6" => bytes[(cursor + 1).min(bytes.len())..]
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as u32)
+                .collect(),
+            _ => return Err(invalid("only P3 and P6 ppm images are supported")),
+        };
+
+        if samples.len() < count {
+            return Err(invalid("ppm image is truncated"));
         }
+
+        let scale = 255.0 / max.max(1) as f32;
+        let pixels = samples[..count]
+            .chunks_exact(3)
+            .map(|c| {
+                let channel = |v: u32| (v as f32 * scale).round().min(255.0) as u8;
+                Color::new(channel(c[0]), channel(c[1]), channel(c[2]))
+            })
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            sums: vec![(Vec3::zero(), 0.0); (width * height) as usize],
+            pixels,
+        })
     }
 
     pub fn save(&self) -> std::io::Result<()> {
@@ -48,6 +142,39 @@ impl PixMap {
         self.pixels[i as usize] = color;
     }
 
+    // Adds a weighted sample to a pixel, which shows the weighted average of all of them.
+    pub fn splat(&mut self, x: u32, y: u32, value: Vec3, weight: f32) {
+        let i = (x + y * self.width) as usize;
+        let (sum, total) = &mut self.sums[i];
+        *sum += value;
+        *total += weight;
+
+        let color = Color::from_weighted(*sum, *total);
+        self.update(x, y, color);
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> &Color {
+        &self.pixels[(x + y * self.width) as usize]
+    }
+
+    // Bilinearly filtered lookup with wrapping, where v runs from the bottom of the image up.
+    pub fn sample(&self, u: f32, v: f32) -> Vec3 {
+        let x = (u - u.floor()) * self.width as f32 - 0.5;
+        let y = (1.0 - (v - v.floor())) * self.height as f32 - 0.5;
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |i: f32, j: f32| -> Vec3 {
+            let i = (i as i64).rem_euclid(self.width as i64) as u32;
+            let j = (j as i64).rem_euclid(self.height as i64) as u32;
+            self.get(i, j).into()
+        };
+
+        (1.0 - fy) * ((1.0 - fx) * texel(x0, y0) + fx * texel(x0 + 1.0, y0))
+            + fy * ((1.0 - fx) * texel(x0, y0 + 1.0) + fx * texel(x0 + 1.0, y0 + 1.0))
+    }
+
     pub fn aspect_ratio(&self) -> f32 {
         self.width as f32 / self.height as f32
     }
@@ -0,0 +1,193 @@
+use std::f32::consts::PI;
+
+use rand::random;
+use ultraviolet::Vec3;
+
+use crate::materials::{reflect, refract, Reflection, Texture};
+use crate::medium::Medium;
+use crate::microfacet::{
+    cosine_hemisphere, fresnel_dielectric, fresnel_schlick, reflection, reflection_pdf,
+    sample_visible_normal, smith_g1, Frame,
+};
+use crate::spectrum::Ior;
+use crate::{Hit, Ray};
+
+// Roughness of the clear coat lobe.
+const CLEARCOAT_ALPHA: f32 = 0.05;
+
+// A single physically based material in the style of the Disney principled BRDF, covering
+// plastics, metals and glass through its parameters. Scalar parameters take the average
+// of their texture and run from 0 to 1.
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Texture,
+    pub metallic: Texture,
+    pub roughness: Texture,
+    pub specular: Texture,
+    pub specular_tint: Texture,
+    pub sheen: Texture,
+    pub clearcoat: Texture,
+    pub transmission: Texture,
+    pub ior: f32,
+}
+
+impl Principled {
+    pub fn new(base_color: Texture) -> Self {
+        Self {
+            base_color,
+            metallic: Texture::constant(0.0),
+            roughness: Texture::constant(0.5),
+            specular: Texture::constant(0.5),
+            specular_tint: Texture::constant(0.0),
+            sheen: Texture::constant(0.0),
+            clearcoat: Texture::constant(0.0),
+            transmission: Texture::constant(0.0),
+            ior: 1.5,
+        }
+    }
+
+    // The glass inside transmissive surfaces, which are never hidden by the media they
+    // overlap. Opaque ones have nothing inside, so their back faces are still solid.
+    pub fn medium(&self) -> Option<Medium> {
+        let opaque = matches!(&self.transmission, Texture::Constant(t) if t.component_max() <= 0.0);
+
+        match opaque {
+            true => None,
+            false => Some(Medium::new(Ior::Constant(self.ior), Vec3::zero(), u32::MAX)),
+        }
+    }
+
+    pub fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Reflection> {
+        let frame = Frame::from_hit(hit);
+        let wo = frame.to_local(-ray.direction().normalized());
+
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let unit = |texture: &Texture| texture.scalar(hit).max(0.0).min(1.0);
+
+        let base = self.base_color.value(hit);
+        let metallic = unit(&self.metallic);
+        let roughness = unit(&self.roughness);
+        let alpha = (roughness * roughness).max(0.001);
+
+        // Transmission replaces the whole reflective part of the material where it applies,
+        // so picking between the two by its weight needs no further correction.
+        let (wi, attenuation) = match random::<f32>() < (1.0 - metallic) * unit(&self.transmission)
+        {
+            true => self.sample_transmission(wo, base, alpha, hit)?,
+            false => self.sample_reflection(wo, base, metallic, roughness, alpha, hit)?,
+        };
+
+        let reflection = Reflection {
+            attenuation,
+            scatter: Ray::new(hit.point, frame.to_world(wi), ray.time()),
+            spectral: false,
+            pass_through: false,
+        };
+
+        Some(reflection)
+    }
+
+    fn sample_transmission(
+        &self,
+        wo: Vec3,
+        base: Vec3,
+        alpha: f32,
+        hit: &Hit,
+    ) -> Option<(Vec3, Vec3)> {
+        let eta_ratio = hit.incident_ior / hit.transmitted_ior;
+        let m = sample_visible_normal(wo, alpha, alpha);
+
+        match random::<f32>() < fresnel_dielectric(wo.dot(m), eta_ratio) {
+            true => {
+                let wi = reflect(-wo, m);
+                match wi.z > 0.0 {
+                    true => Some((wi, Vec3::broadcast(smith_g1(wi, alpha, alpha)))),
+                    false => None,
+                }
+            }
+            false => {
+                let wi = refract(-wo, m, eta_ratio);
+                let below = Vec3::new(wi.x, wi.y, -wi.z);
+                match wi.z < 0.0 {
+                    true => Some((wi, base * smith_g1(below, alpha, alpha))),
+                    false => None,
+                }
+            }
+        }
+    }
+
+    fn sample_reflection(
+        &self,
+        wo: Vec3,
+        base: Vec3,
+        metallic: f32,
+        roughness: f32,
+        alpha: f32,
+        hit: &Hit,
+    ) -> Option<(Vec3, Vec3)> {
+        let unit = |texture: &Texture| texture.scalar(hit).max(0.0).min(1.0);
+
+        let luminance = base.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+        let tint = match luminance > 0.0 {
+            true => base / luminance,
+            false => Vec3::one(),
+        };
+
+        let specular_color = lerp(Vec3::one(), tint, unit(&self.specular_tint));
+        let f0 = lerp(0.08 * unit(&self.specular) * specular_color, base, metallic);
+
+        let diffuse_weight = 1.0 - metallic;
+        let coat_weight = 0.25 * unit(&self.clearcoat);
+        let total = diffuse_weight + 1.0 + coat_weight;
+
+        let choice = random::<f32>() * total;
+        let wi = if choice < diffuse_weight {
+            cosine_hemisphere()
+        } else if choice < diffuse_weight + 1.0 {
+            reflect(-wo, sample_visible_normal(wo, alpha, alpha))
+        } else {
+            let m = sample_visible_normal(wo, CLEARCOAT_ALPHA, CLEARCOAT_ALPHA);
+            reflect(-wo, m)
+        };
+
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        let h = (wo + wi).normalized();
+        let cos_d = wi.dot(h);
+        let schlick_weight = (1.0 - cos_d).max(0.0).powi(5);
+
+        // Burley's diffuse with its grazing retro-reflection, plus sheen at grazing angles.
+        let fd90 = 0.5 + 2.0 * roughness * cos_d * cos_d;
+        let fd = |cosine: f32| 1.0 + (fd90 - 1.0) * (1.0 - cosine).powi(5);
+        let sheen = unit(&self.sheen) * lerp(Vec3::one(), tint, 0.5) * schlick_weight;
+        let diffuse = diffuse_weight * (base / PI * fd(wo.z) * fd(wi.z) + sheen);
+
+        let specular = fresnel_schlick(f0, cos_d) * reflection(wo, wi, alpha, alpha);
+
+        let coat_fresnel = 0.04 + 0.96 * schlick_weight;
+        let coat =
+            coat_weight * coat_fresnel * reflection(wo, wi, CLEARCOAT_ALPHA, CLEARCOAT_ALPHA);
+
+        let pdf = (diffuse_weight * wi.z / PI
+            + reflection_pdf(wo, wi, alpha, alpha)
+            + coat_weight * reflection_pdf(wo, wi, CLEARCOAT_ALPHA, CLEARCOAT_ALPHA))
+            / total;
+
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        let f = diffuse + specular + Vec3::broadcast(coat);
+
+        Some((wi, f * wi.z / pdf))
+    }
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    (1.0 - t) * a + t * b
+}
@@ -1,18 +1,39 @@
+use rand::random;
 use ultraviolet::Vec3;
 
-use crate::materials::Scatter;
+use crate::materials::{henyey_greenstein, Scatter};
+use crate::medium::MediumStack;
+use crate::spectrum::{self, D_LINE};
 use crate::world::World;
 use crate::Hittable;
 
+// Bounces after which paths may be ended by Russian roulette.
+const ROULETTE_DEPTH: u32 = 8;
+
+// Scattering events a single random walk through a medium may take before it is ended.
+const MAX_WALK_STEPS: u32 = 1024;
+
 pub struct Ray {
     origin: Vec3,
     vec: Vec3,
     time: f32,
+    // The hero and secondary wavelengths carried in spectral mode, in nanometres.
+    wavelengths: Option<Vec3>,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, vec: Vec3, time: f32) -> Self {
-        Ray { origin, vec, time }
+        Ray {
+            origin,
+            vec,
+            time,
+            wavelengths: None,
+        }
+    }
+
+    pub fn with_wavelengths(mut self, wavelengths: Option<Vec3>) -> Self {
+        self.wavelengths = wavelengths;
+        self
     }
 
     pub fn origin(&self) -> Vec3 {
@@ -27,6 +48,18 @@ impl Ray {
         self.time
     }
 
+    pub fn wavelengths(&self) -> Option<Vec3> {
+        self.wavelengths
+    }
+
+    // An RGB color as the spectrum seen at this ray's wavelengths, if it carries any.
+    pub fn spectral(&self, rgb: Vec3) -> Vec3 {
+        match self.wavelengths {
+            Some(wavelengths) => spectrum::upsample(rgb, wavelengths),
+            None => rgb,
+        }
+    }
+
     pub fn at(&self, t: f32) -> Vec3 {
         self.origin + self.vec * t
     }
@@ -41,20 +74,114 @@ impl Ray {
         c
     }
 
+    // Follows the path bounce by bounce, gathering the light emitted along it scaled by the
+    // throughput of the bounces before, until it escapes to the sky or runs out of depth.
     pub fn trace(&self, world: &World, depth: u32) -> Vec3 {
-        if depth <= 0 {
-            // Black if we have exceeded the max depth.
-            return Vec3::default();
-        }
+        let mut media = MediumStack::new();
+        let mut ray = Ray::new(self.origin, self.vec, self.time).with_wavelengths(self.wavelengths);
+        let mut throughput = Vec3::one();
+        let mut radiance = Vec3::zero();
+        let mut walk = 0;
 
-        match world.hit(&self, 0.001, f32::INFINITY) {
-            Some(hit) => match hit.material.scatter(&self, &hit) {
-                Some(reflection) => {
-                    reflection.attenuation * reflection.scatter.trace(world, depth - 1)
+        for bounce in 0..depth {
+            // Paths carrying little light are ended at random, with the survivors weighted up
+            // to make up for them, so that walks through dense volumes stop long before the
+            // max depth.
+            if bounce >= ROULETTE_DEPTH {
+                let survival = throughput.component_max().min(0.95);
+                if random::<f32>() >= survival {
+                    return radiance;
                 }
-                None => Vec3::default(),
-            },
-            None => self.color(),
+                throughput /= survival;
+            }
+
+            let mut hit = match world.hit(&ray, 0.001, f32::INFINITY) {
+                Some(hit) => hit,
+                None => return radiance + throughput * ray.spectral(ray.color()),
+            };
+
+            let current = media.current();
+            let length = ray.direction().mag();
+            let wavelength = ray.wavelengths.map_or(D_LINE, |w| w.x);
+
+            match current.free_flight(hit.t * length) {
+                (Some(distance), weight) => {
+                    walk += 1;
+                    if walk > MAX_WALK_STEPS {
+                        return radiance;
+                    }
+
+                    throughput *= ray.spectral(weight);
+
+                    let direction = henyey_greenstein(ray.direction() / length, current.anisotropy);
+                    ray = Ray::new(ray.at(distance / length), direction, ray.time())
+                        .with_wavelengths(ray.wavelengths);
+                    continue;
+                }
+                (None, weight) => throughput *= ray.spectral(weight),
+            }
+
+            walk = 0;
+
+            let boundary = hit.material.medium();
+            let mut dispersive = false;
+
+            if let Some(medium) = boundary {
+                let (incident, beyond) = match media.current_after(medium, hit.front_face) {
+                    // The surface is hidden by a higher priority medium, so the ray carries
+                    // on straight through it.
+                    Some(beyond) if beyond == current => {
+                        media.cross(medium, hit.front_face);
+                        ray = Ray::new(hit.point, ray.direction(), ray.time())
+                            .with_wavelengths(ray.wavelengths);
+                        continue;
+                    }
+                    Some(beyond) => (current, beyond),
+                    // Leaving a medium the path never entered, like the back of an open
+                    // surface, is still an interface out of it into the current medium.
+                    None => (medium, current),
+                };
+
+                hit.incident_ior = incident.ior.at(wavelength);
+                hit.transmitted_ior = beyond.ior.at(wavelength);
+                dispersive = incident.ior.is_dispersive() || beyond.ior.is_dispersive();
+            }
+
+            radiance += throughput * ray.spectral(hit.material.emitted(&hit));
+
+            let reflection = match hit.material.scatter(&ray, &hit) {
+                Some(reflection) => reflection,
+                None => return radiance,
+            };
+
+            let mut attenuation = match reflection.spectral {
+                true => reflection.attenuation,
+                false => ray.spectral(reflection.attenuation),
+            };
+            let mut wavelengths = ray.wavelengths;
+
+            // Rays through the gaps of a cutout carry on without entering what is behind.
+            if let Some(medium) = boundary.filter(|_| !reflection.pass_through) {
+                if reflection.scatter.direction().dot(hit.normal) < 0.0 {
+                    media.cross(medium, hit.front_face);
+
+                    // Only the hero wavelength follows the refracted direction, so the others
+                    // are terminated and the hero carries their weight for the rest of the path.
+                    match wavelengths {
+                        Some(w) if dispersive && w.y != w.x => {
+                            attenuation *= Vec3::new(3.0, 0.0, 0.0);
+                            wavelengths = Some(Vec3::broadcast(w.x));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            throughput *= attenuation;
+            ray = reflection.scatter.with_wavelengths(wavelengths);
         }
+
+        // Black beyond the max depth.
+        radiance
     }
 }
@@ -1,5 +1,7 @@
+use std::f32::consts::PI;
+
 use rand::random;
-use ultraviolet::Vec3;
+use ultraviolet::{Vec2, Vec3};
 
 use crate::materials::Material;
 // use crate::matrix::Vec3;
@@ -10,6 +12,8 @@ pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
     material: Material,
+    // How far the center moves per second, with `center` being where it is at time zero.
+    velocity: Vec3,
 }
 
 impl Sphere {
@@ -18,9 +22,19 @@ impl Sphere {
             center,
             radius,
             material,
+            velocity: Vec3::zero(),
         }
     }
 
+    pub fn set_velocity(&mut self, velocity: Vec3) -> &mut Self {
+        self.velocity = velocity;
+        self
+    }
+
+    fn center_at(&self, time: f32) -> Vec3 {
+        self.center + self.velocity * time
+    }
+
     pub fn unit() -> Self {
         Self::new(Vec3::default(), 1.0, Material::default())
     }
@@ -38,11 +52,41 @@ impl Sphere {
     fn contains(&self, point: Vec3) -> bool {
         (point - self.center).mag_sq() < self.radius
     }
+
+    fn hit_at(&self, ray: &Ray, t: f32) -> Hit {
+        let center = self.center_at(ray.time());
+        let point = ray.at(t);
+        let normal = (point - center) / self.radius;
+
+        let mut hit = Hit::new(t, point, normal, false, self.material.clone());
+        hit.set_face_normal(ray, normal);
+
+        // Spherical coordinates, with u running around the y axis and v from pole to pole.
+        let theta = (-normal.y).max(-1.0).min(1.0).acos();
+        let phi = (-normal.z).atan2(normal.x) + PI;
+        let uv = Vec2::new(phi / (2.0 * PI), theta / PI);
+
+        let local = point - center;
+        let ring = (local.x * local.x + local.z * local.z).sqrt();
+
+        let dpdu = 2.0 * PI * Vec3::new(local.z, 0.0, -local.x);
+        let dpdv = match ring > 0.0 {
+            true => {
+                let slope = local.y / ring;
+                PI * Vec3::new(-local.x * slope, ring, -local.z * slope)
+            }
+            false => Vec3::zero(),
+        };
+
+        hit.set_surface(uv, dpdu, dpdv);
+
+        hit
+    }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, min: f32, max: f32) -> Option<Hit> {
-        let oc = ray.origin() - self.center;
+        let oc = ray.origin() - self.center_at(ray.time());
 
         let a = ray.direction().dot(ray.direction());
         let half_b = oc.dot(ray.direction());
@@ -55,27 +99,81 @@ impl Hittable for Sphere {
 
             let t = (-half_b - root) / a;
             if t > min && t < max {
-                let point = ray.at(t);
-                let normal = (point - self.center) / self.radius;
-
-                let mut hit = Hit::new(t, point, normal, false, self.material.clone());
-                hit.set_face_normal(ray, normal);
-
-                return Some(hit);
+                return Some(self.hit_at(ray, t));
             }
 
             let t = (-half_b + root) / a;
             if t > min && t < max {
-                let point = ray.at(t);
-                let normal = (point - self.center) / self.radius;
-
-                let mut hit = Hit::new(t, point, normal, false, self.material.clone());
-                hit.set_face_normal(ray, normal);
-
-                return Some(hit);
+                return Some(self.hit_at(ray, t));
             }
         }
 
         None
     }
+
+    fn centroid(&self) -> Option<Vec3> {
+        Some(self.center)
+    }
+}
+
+// A flat parallelogram spanned by two edges from a corner, with uv coordinates running
+// along each edge.
+#[derive(Clone)]
+pub struct Quad {
+    corner: Vec3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    material: Material,
+}
+
+impl Quad {
+    pub fn new(corner: Vec3, u: Vec3, v: Vec3, material: Material) -> Self {
+        Quad {
+            corner,
+            u,
+            v,
+            normal: u.cross(v).normalized(),
+            material,
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, min: f32, max: f32) -> Option<Hit> {
+        let denominator = self.normal.dot(ray.direction());
+
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = self.normal.dot(self.corner - ray.origin()) / denominator;
+
+        if t <= min || t >= max {
+            return None;
+        }
+
+        let point = ray.at(t);
+        let planar = point - self.corner;
+
+        // Coordinates of the point along each edge, through the dual basis of the plane.
+        let n = self.u.cross(self.v);
+        let w = n / n.mag_sq();
+        let alpha = w.dot(planar.cross(self.v));
+        let beta = w.dot(self.u.cross(planar));
+
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        let mut hit = Hit::new(t, point, self.normal, false, self.material.clone());
+        hit.set_face_normal(ray, self.normal);
+        hit.set_surface(Vec2::new(alpha, beta), self.u, self.v);
+
+        Some(hit)
+    }
+
+    fn centroid(&self) -> Option<Vec3> {
+        Some(self.corner + (self.u + self.v) / 2.0)
+    }
 }
@@ -0,0 +1,110 @@
+use rand::random;
+use ultraviolet::Vec3;
+
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 730.0;
+
+// The sodium d-line, where indices of refraction are usually quoted and evaluated outside of
+// spectral mode.
+pub const D_LINE: f32 = 587.6;
+
+// An index of refraction which may vary with wavelength. Dispersion coefficients take the
+// wavelength in micrometres, as they are tabulated in glass catalogues.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ior {
+    Constant(f32),
+    // n = A + B / λ²
+    Cauchy(f32, f32),
+    // n² = 1 + Σ Bᵢ λ² / (λ² - Cᵢ)
+    Sellmeier([f32; 3], [f32; 3]),
+}
+
+impl Ior {
+    pub fn at(&self, wavelength: f32) -> f32 {
+        let micrometres = wavelength / 1000.0;
+        let sq = micrometres * micrometres;
+
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy(a, b) => a + b / sq,
+            Ior::Sellmeier(b, c) => {
+                let sum: f32 = (0..3).map(|i| b[i] * sq / (sq - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+impl Default for Ior {
+    fn default() -> Self {
+        Ior::Constant(1.0)
+    }
+}
+
+// Hero wavelength sampling: a uniformly random hero wavelength in x, and two more spaced
+// evenly across the visible range from it.
+pub fn sample_wavelengths() -> Vec3 {
+    let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+    let hero = random::<f32>() * range;
+
+    let rotate = |i: f32| MIN_WAVELENGTH + (hero + i * range / 3.0) % range;
+
+    Vec3::new(rotate(0.0), rotate(1.0), rotate(2.0))
+}
+
+// Upsamples an RGB color to its spectrum at the given wavelengths, with a box basis that
+// splits the visible range into a blue, green and red band. Being a plain choice of channel,
+// products of colors upsample to the products of their spectra.
+pub fn upsample(rgb: Vec3, wavelengths: Vec3) -> Vec3 {
+    let band = |wavelength: f32| match wavelength {
+        w if w < 490.0 => rgb.z,
+        w if w < 590.0 => rgb.y,
+        _ => rgb.x,
+    };
+
+    wavelengths.map(band)
+}
+
+// Converts the radiance carried at the given wavelengths to linear sRGB, balanced so that a
+// constant spectrum comes out white.
+pub fn to_rgb(radiance: Vec3, wavelengths: Vec3) -> Vec3 {
+    let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+    let xyz = cie_xyz(wavelengths.x) * radiance.x
+        + cie_xyz(wavelengths.y) * radiance.y
+        + cie_xyz(wavelengths.z) * radiance.z;
+    let xyz = xyz * range / 3.0;
+
+    let rgb = Vec3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    );
+
+    // The sRGB color of a constant unit spectrum over the sampled range.
+    let white = Vec3::new(128.364, 101.544, 97.050);
+
+    rgb / white
+}
+
+// The CIE 1931 color matching functions, from the multi-lobe fit of Wyman, Sloan and Shirley.
+pub fn cie_xyz(wavelength: f32) -> Vec3 {
+    let lobe = |mean: f32, below: f32, above: f32| {
+        let sigma = match wavelength < mean {
+            true => below,
+            false => above,
+        };
+        let t = (wavelength - mean) / sigma;
+        (-0.5 * t * t).exp()
+    };
+
+    Vec3::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
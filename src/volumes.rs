@@ -0,0 +1,262 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::random;
+use ultraviolet::Vec3;
+
+use crate::materials::{Material, PerlinNoise};
+use crate::{Hit, Hittable, Ray};
+
+// A volume of uniform density filling the inside of a closed boundary shape. The material
+// should be a phase function such as `Material::Isotropic`.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable + Send + Sync>,
+    density: f32,
+    phase: Material,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Box<dyn Hittable + Send + Sync>, density: f32, phase: Material) -> Self {
+        Self {
+            boundary,
+            density,
+            phase,
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, min: f32, max: f32) -> Option<Hit> {
+        let entry = self.boundary.hit(ray, f32::NEG_INFINITY, f32::INFINITY)?;
+        let exit = self.boundary.hit(ray, entry.t + 0.0001, f32::INFINITY)?;
+
+        let near = entry.t.max(min).max(0.0);
+        let far = exit.t.min(max);
+
+        if near >= far {
+            return None;
+        }
+
+        let length = ray.direction().mag();
+        let distance = -random::<f32>().ln() / self.density;
+
+        if distance > (far - near) * length {
+            return None;
+        }
+
+        let t = near + distance / length;
+
+        // Volumes have no surface, so the normal is arbitrary.
+        let hit = Hit::new(t, ray.at(t), Vec3::unit_x(), true, self.phase.clone());
+
+        Some(hit)
+    }
+
+    fn hit_surface(&self, _ray: &Ray, _min: f32, _max: f32) -> Option<Hit> {
+        None
+    }
+
+    fn centroid(&self) -> Option<Vec3> {
+        self.boundary.centroid()
+    }
+}
+
+#[derive(Clone)]
+pub enum Density {
+    // Noise sampled at the given frequency, scaled up to the given peak density.
+    Noise(PerlinNoise, f32, f32),
+    Grid(Arc<VoxelGrid>),
+}
+
+impl Density {
+    pub fn at(&self, point: Vec3) -> f32 {
+        match self {
+            Self::Noise(noise, frequency, peak) => {
+                let n = noise.noise(&(point * *frequency));
+                peak * (0.5 * (1.0 + n)).max(0.0).min(1.0)
+            }
+            Self::Grid(grid) => grid.density(point),
+        }
+    }
+
+    // An upper bound on the density anywhere in the volume.
+    pub fn majorant(&self) -> f32 {
+        match self {
+            Self::Noise(_, _, peak) => *peak,
+            Self::Grid(grid) => grid.peak,
+        }
+    }
+}
+
+// A dense grid of density values stretched over an axis aligned box.
+pub struct VoxelGrid {
+    dimensions: [usize; 3],
+    values: Vec<f32>,
+    min: Vec3,
+    max: Vec3,
+    peak: f32,
+}
+
+impl VoxelGrid {
+    pub fn new(dimensions: [usize; 3], values: Vec<f32>, min: Vec3, max: Vec3) -> Self {
+        let peak = values.iter().cloned().fold(0.0, f32::max);
+
+        Self {
+            dimensions,
+            values,
+            min,
+            max,
+            peak,
+        }
+    }
+
+    // Reads a grid stored as three little endian u32 dimensions followed by the f32 density
+    // of every voxel, with x varying fastest.
+    pub fn load<P: AsRef<Path>>(path: P, min: Vec3, max: Vec3) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        if bytes.len() < 12 {
+            return Err(invalid("voxel grid header is truncated"));
+        }
+
+        let words = bytes
+            .chunks_exact(4)
+            .map(|c| [c[0], c[1], c[2], c[3]])
+            .collect::<Vec<_>>();
+
+        let dimensions = [
+            u32::from_le_bytes(words[0]) as usize,
+            u32::from_le_bytes(words[1]) as usize,
+            u32::from_le_bytes(words[2]) as usize,
+        ];
+
+        if dimensions.contains(&0) {
+            return Err(invalid("voxel grid dimensions must not be zero"));
+        }
+
+        let count = dimensions[0]
+            .checked_mul(dimensions[1])
+            .and_then(|n| n.checked_mul(dimensions[2]))
+            .ok_or_else(|| invalid("voxel grid dimensions are too large"))?;
+        if words.len() - 3 != count {
+            return Err(invalid("voxel grid size does not match its dimensions"));
+        }
+
+        let values = words[3..].iter().map(|w| f32::from_le_bytes(*w)).collect();
+
+        Ok(Self::new(dimensions, values, min, max))
+    }
+
+    // Trilinearly interpolated density, zero outside of the grid bounds.
+    pub fn density(&self, point: Vec3) -> f32 {
+        let extent = self.max - self.min;
+        let local = (point - self.min) / extent;
+
+        if local.component_min() < 0.0 || local.component_max() > 1.0 {
+            return 0.0;
+        }
+
+        let [nx, ny, nz] = self.dimensions;
+        let x = local.x * (nx - 1) as f32;
+        let y = local.y * (ny - 1) as f32;
+        let z = local.z * (nz - 1) as f32;
+
+        let (i, j, k) = (x as usize, y as usize, z as usize);
+        let (u, v, w) = (x - i as f32, y - j as f32, z - k as f32);
+
+        let mut density = 0.0;
+        for (di, wx) in [(0, 1.0 - u), (1, u)].iter() {
+            for (dj, wy) in [(0, 1.0 - v), (1, v)].iter() {
+                for (dk, wz) in [(0, 1.0 - w), (1, w)].iter() {
+                    let index = (i + di).min(nx - 1)
+                        + (j + dj).min(ny - 1) * nx
+                        + (k + dk).min(nz - 1) * nx * ny;
+                    density += wx * wy * wz * self.values[index];
+                }
+            }
+        }
+
+        density
+    }
+}
+
+// A volume whose density varies throughout its boundary shape, sampled with delta tracking
+// against the density majorant.
+pub struct HeterogeneousMedium {
+    boundary: Box<dyn Hittable + Send + Sync>,
+    density: Density,
+    phase: Material,
+    emission: Option<(Material, f32)>,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(
+        boundary: Box<dyn Hittable + Send + Sync>,
+        density: Density,
+        phase: Material,
+    ) -> Self {
+        Self {
+            boundary,
+            density,
+            phase,
+            emission: None,
+        }
+    }
+
+    // Makes the given fraction of collisions absorb the path and emit light instead of
+    // scattering, such as the glowing core of a fire.
+    pub fn set_emission(&mut self, emission: Material, absorption: f32) -> &mut Self {
+        self.emission = Some((emission, absorption));
+        self
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, ray: &Ray, min: f32, max: f32) -> Option<Hit> {
+        let entry = self.boundary.hit(ray, f32::NEG_INFINITY, f32::INFINITY)?;
+        let exit = self.boundary.hit(ray, entry.t + 0.0001, f32::INFINITY)?;
+
+        let near = entry.t.max(min).max(0.0);
+        let far = exit.t.min(max);
+
+        let majorant = self.density.majorant();
+        if near >= far || majorant <= 0.0 {
+            return None;
+        }
+
+        let length = ray.direction().mag();
+        let mut t = near;
+
+        loop {
+            t -= (1.0 - random::<f32>()).ln() / (majorant * length);
+
+            if t >= far {
+                return None;
+            }
+
+            let point = ray.at(t);
+
+            // Collisions with the fictitious density that tops the volume up to the
+            // majorant leave the ray untouched.
+            if random::<f32>() * majorant < self.density.at(point) {
+                let material = match &self.emission {
+                    Some((emission, absorption)) if random::<f32>() < *absorption => emission,
+                    _ => &self.phase,
+                };
+
+                return Some(Hit::new(t, point, Vec3::unit_x(), true, material.clone()));
+            }
+        }
+    }
+
+    fn hit_surface(&self, _ray: &Ray, _min: f32, _max: f32) -> Option<Hit> {
+        None
+    }
+
+    fn centroid(&self) -> Option<Vec3> {
+        self.boundary.centroid()
+    }
+}
@@ -1,14 +1,20 @@
+use std::collections::HashMap;
+
+use ultraviolet::Vec3;
+
 use crate::ray::Ray;
 use crate::{Hit, Hittable};
 
 pub struct World {
     objects: Vec<Box<dyn Hittable + Send + Sync>>,
+    names: HashMap<String, usize>,
 }
 
 impl World {
     pub fn new() -> Self {
         Self {
             objects: Vec::new(),
+            names: HashMap::new(),
         }
     }
 
@@ -16,6 +22,24 @@ impl World {
         self.objects.push(object);
         self
     }
+
+    // Adds an object which can be looked up by name later, such as to focus the camera on.
+    pub fn add_named_object(
+        &mut self,
+        name: &str,
+        object: Box<dyn Hittable + Send + Sync>,
+    ) -> &mut Self {
+        self.names.insert(name.to_string(), self.objects.len());
+        self.add_object(object)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&(dyn Hittable + Send + Sync)> {
+        self.names.get(name).map(|&i| self.objects[i].as_ref())
+    }
+
+    pub fn centroid(&self, name: &str) -> Option<Vec3> {
+        self.get(name)?.centroid()
+    }
 }
 
 impl Hittable for World {
@@ -29,4 +53,15 @@ impl Hittable for World {
 
         Some(hit).flatten()
     }
+
+    fn hit_surface(&self, ray: &Ray, min: f32, max: f32) -> Option<Hit> {
+        let (_closest, hit) = self.objects.iter().fold((max, None), |acc, object| {
+            match object.hit_surface(ray, min, acc.0) {
+                Some(hit) => (hit.t, Some(hit)),
+                None => acc,
+            }
+        });
+
+        Some(hit).flatten()
+    }
 }